@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+use crate::{
+    config::{CommandLine, Program, RunPolicy, StartUp},
+    startup::run_start_up,
+};
+
+const DESKTOP_NAME: &str = "sswm";
+
+// Only the keys of the [Desktop Entry] group, localized ones are skipped
+fn parse_entry(contents: &str) -> BTreeMap<String, String> {
+    let mut keys = BTreeMap::new();
+    let mut in_entry = false;
+
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if let (true, Some((key, value))) = (in_entry, line.split_once('=')) {
+            let key = key.trim();
+            if !key.contains('[') && !line.starts_with('#') {
+                keys.insert(key.to_string(), value.trim().to_string());
+            }
+        }
+    }
+
+    keys
+}
+
+fn is_true(keys: &BTreeMap<String, String>, key: &str) -> bool {
+    keys.get(key)
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+fn desktop_names() -> Vec<String> {
+    let mut names: Vec<String> = env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(':')
+        .filter(|n| !n.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    names.push(DESKTOP_NAME.into());
+    names
+}
+
+fn shown_in(keys: &BTreeMap<String, String>, desktops: &[String]) -> bool {
+    let matches = |key: &str| {
+        keys.get(key).map(|list| {
+            list.split(';')
+                .any(|name| desktops.contains(&name.trim().to_lowercase()))
+        })
+    };
+
+    matches("OnlyShowIn").unwrap_or(true) && !matches("NotShowIn").unwrap_or(false)
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+fn try_exec(program: &str) -> bool {
+    if program.contains('/') {
+        return is_executable(Path::new(program));
+    }
+
+    env::var_os("PATH")
+        .is_some_and(|path| env::split_paths(&path).any(|dir| is_executable(&dir.join(program))))
+}
+
+// Field codes like %f or %U stand for files and urls, which an autostarted
+// program never gets.
+fn exec_argv(exec: &str) -> Option<Vec<String>> {
+    let argv = shell_words::split(exec).ok()?;
+
+    let argv: Vec<String> = argv
+        .into_iter()
+        .filter(|arg| !(arg.len() == 2 && arg.starts_with('%') && arg != "%%"))
+        .map(|arg| arg.replace("%%", "%"))
+        .collect();
+
+    (!argv.is_empty()).then_some(argv)
+}
+
+fn entry_program(path: &Path, desktops: &[String]) -> Option<Program> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| warn!("unable to read {}: {e}", path.display()))
+        .ok()?;
+    let keys = parse_entry(&contents);
+
+    if is_true(&keys, "Hidden")
+        || keys
+            .get("X-GNOME-Autostart-enabled")
+            .is_some_and(|v| v.eq_ignore_ascii_case("false"))
+        || !shown_in(&keys, desktops)
+        || keys.get("TryExec").is_some_and(|p| !try_exec(p))
+    {
+        info!("not autostarting {}", path.display());
+        return None;
+    }
+
+    Some(Program {
+        command: CommandLine::Argv(exec_argv(keys.get("Exec")?)?),
+        cwd: keys.get("Path").filter(|p| !p.is_empty()).cloned(),
+        run: RunPolicy::Once,
+        ..Default::default()
+    })
+}
+
+// Directories from the least to the most important one, so an entry in
+// ~/.config/autostart replaces (or hides) one with the same name in /etc/xdg.
+fn autostart_dirs() -> Vec<PathBuf> {
+    let config_dirs = env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".into());
+    let mut dirs: Vec<PathBuf> = config_dirs
+        .split(':')
+        .rev()
+        .map(|d| Path::new(d).join("autostart"))
+        .collect();
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(config_home) = config_home {
+        dirs.push(config_home.join("autostart"));
+    }
+
+    dirs
+}
+
+pub fn run_xdg_autostart() {
+    // Display managers set this from DesktopNames, sswm started by hand doesn't get it
+    if env::var_os("XDG_CURRENT_DESKTOP").is_none() {
+        env::set_var("XDG_CURRENT_DESKTOP", DESKTOP_NAME);
+    }
+
+    let mut entries = BTreeMap::new();
+    for dir in autostart_dirs() {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for path in read_dir.flatten().map(|e| e.path()) {
+            if path.extension().is_some_and(|ext| ext == "desktop") {
+                if let Some(name) = path.file_name() {
+                    entries.insert(name.to_os_string(), path);
+                }
+            }
+        }
+    }
+
+    let desktops = desktop_names();
+    let programs: Vec<StartUp> = entries
+        .values()
+        .filter_map(|path| entry_program(path, &desktops))
+        .map(StartUp::Program)
+        .collect();
+
+    run_start_up(&programs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entry_reads_the_desktop_entry_group() {
+        let keys = parse_entry(
+            "# comment\n\
+             [Desktop Entry]\n\
+             Type=Application\n\
+             Name=Network\n\
+             Name[de]=Netzwerk\n\
+             Exec = nm-applet --indicator \n\
+             # Hidden=true\n\
+             \n\
+             [Desktop Action New]\n\
+             Exec=nm-applet --new\n",
+        );
+
+        assert_eq!(keys.get("Type").map(String::as_str), Some("Application"));
+        assert_eq!(keys.get("Name").map(String::as_str), Some("Network"));
+        assert_eq!(
+            keys.get("Exec").map(String::as_str),
+            Some("nm-applet --indicator")
+        );
+        assert!(!keys.contains_key("Name[de]"));
+        assert!(!keys.contains_key("# Hidden"));
+        assert_eq!(keys.len(), 3);
+    }
+
+    #[test]
+    fn parse_entry_ignores_keys_outside_the_group() {
+        let keys = parse_entry("Exec=before\n[Other]\nExec=other\n");
+        assert!(keys.is_empty());
+    }
+
+    #[test]
+    fn parse_entry_keeps_equals_in_values() {
+        let keys = parse_entry("[Desktop Entry]\nExec=env FOO=bar baz\n");
+        assert_eq!(
+            keys.get("Exec").map(String::as_str),
+            Some("env FOO=bar baz")
+        );
+    }
+}
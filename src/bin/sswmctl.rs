@@ -0,0 +1,100 @@
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    process::exit,
+};
+
+// Only the requests and responses are used on this end
+#[allow(dead_code)]
+#[path = "../ipc/protocol.rs"]
+mod protocol;
+
+use protocol::{Request, Response, SOCKET_ENV};
+
+const USAGE: &str = "usage: sswmctl <command>
+
+commands:
+    tags                list the tags
+    windows             list every managed window
+    outputs             list the outputs
+    layouts             show the layouts of the focused tag
+    focus               show the focused tag and window
+    action <action>     run an action, e.g. `action ToggleScratchpad term`
+    exec <command>      spawn a command
+    shell <command>     spawn a command through `sh -c`
+    subscribe [event..] print events as they happen, all of them by default";
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+    eprintln!("sswmctl: {msg}");
+    exit(1)
+}
+
+fn parse_request(args: &[String]) -> Option<Request> {
+    let rest = args.get(1..).unwrap_or_default().join(" ");
+
+    Some(match args.first()?.as_str() {
+        "tags" => Request::GetTags,
+        "windows" => Request::GetWindows,
+        "outputs" => Request::GetOutputs,
+        "layouts" => Request::GetLayouts,
+        "focus" => Request::GetFocus,
+        "action" if !rest.is_empty() => Request::RunAction { action: rest },
+        "exec" if !rest.is_empty() => Request::RunCommand {
+            command: rest,
+            shell: false,
+        },
+        "shell" if !rest.is_empty() => Request::RunCommand {
+            command: rest,
+            shell: true,
+        },
+        "subscribe" => Request::Subscribe {
+            events: args[1..].to_vec(),
+        },
+        _ => return None,
+    })
+}
+
+fn send(request: &Request) -> std::io::Result<(Response, BufReader<UnixStream>)> {
+    let path = env::var_os(SOCKET_ENV).unwrap_or_else(|| fail(format!("{SOCKET_ENV} isn't set")));
+    let mut stream = UnixStream::connect(path)?;
+
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok((serde_json::from_str(&line)?, reader))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(request) = parse_request(&args) else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+
+    let (response, reader) = send(&request).unwrap_or_else(|e| fail(e));
+    if !response.success {
+        fail(response.error.unwrap_or_else(|| "request failed".into()));
+    }
+
+    // Events are passed through as they come, one JSON object per line
+    if let Request::Subscribe { .. } = request {
+        for line in reader.lines() {
+            match line {
+                Ok(line) => println!("{line}"),
+                Err(e) => fail(e),
+            }
+        }
+        return;
+    }
+
+    if let Some(data) = response.data {
+        match serde_json::to_string_pretty(&data) {
+            Ok(json) => println!("{json}"),
+            Err(e) => fail(e),
+        }
+    }
+}
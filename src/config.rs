@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Action {
@@ -6,10 +7,327 @@ pub struct Action {
     pub action: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+// Either a single line split with shell-like quoting, or the argv as a list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Line(String),
+    Argv(Vec<String>),
+}
+
+impl Default for CommandLine {
+    fn default() -> Self {
+        CommandLine::Line(String::new())
+    }
+}
+
+impl From<&str> for CommandLine {
+    fn from(line: &str) -> Self {
+        CommandLine::Line(line.into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Restart {
+    #[default]
+    Never,
+    OnFailure,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunPolicy {
+    // Every time sswm starts, also when it is restarted in the same session
+    #[default]
+    Always,
+    // Only the first time sswm starts in a login session
+    Once,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Program {
+    pub command: CommandLine,
+    #[serde(default)]
+    pub shell: bool,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub restart: Restart,
+    #[serde(default)]
+    pub run: RunPolicy,
+}
+
+// Plain strings are shell snippets, like the whole list used to be
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum StartUp {
+    Line(String),
+    Program(Program),
+}
+
+impl StartUp {
+    pub fn program(&self) -> Program {
+        match self {
+            StartUp::Line(line) => Program {
+                command: CommandLine::Line(line.clone()),
+                shell: true,
+                ..Default::default()
+            },
+            StartUp::Program(program) => program.clone(),
+        }
+    }
+}
+
+impl Program {
+    pub fn command(&self) -> Command {
+        Command {
+            keybind: String::new(),
+            command: self.command.clone(),
+            shell: self.shell,
+            env: self.env.clone(),
+            cwd: self.cwd.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct Command {
     pub keybind: String,
-    pub command: String,
+    pub command: CommandLine,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shell: bool,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Mode {
+    pub name: String,
+    pub commands: Vec<Command>,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct MouseBinding {
+    pub mousebind: String,
+    pub action: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Keyboard {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: String,
+    pub repeat_delay: Option<u32>,
+    pub repeat_rate: Option<u32>,
+}
+
+impl Keyboard {
+    // Used by both backends for whichever of the two isn't set
+    pub fn repeat(&self) -> (u32, u32) {
+        (
+            self.repeat_delay.unwrap_or(500),
+            self.repeat_rate.unwrap_or(25),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Bar {
+    pub enabled: bool,
+    pub height: u32,
+    pub font: String,
+    pub point_size: u8,
+    pub foreground: u32,
+    pub background: u32,
+    pub highlight: u32,
+    pub empty: u32,
+    pub tray: bool,
+    pub tray_monitor: usize,
+}
+
+impl Default for Bar {
+    fn default() -> Self {
+        Bar {
+            enabled: false,
+            height: 18,
+            font: "monospace".into(),
+            point_size: 8,
+            foreground: 0xebdbb2ff,
+            background: 0x282828ff,
+            highlight: 0x458588ff,
+            empty: 0x928374ff,
+            tray: false,
+            tray_monitor: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KeyChords {
+    pub timeout: u64,
+    pub hint_command: Option<String>,
+}
+
+impl Default for KeyChords {
+    fn default() -> Self {
+        KeyChords {
+            timeout: 1000,
+            hint_command: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scratchpad {
+    pub name: String,
+    pub command: CommandLine,
+    #[serde(default)]
+    pub shell: bool,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub class: Option<String>,
+    #[serde(default)]
+    pub instance: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default = "Scratchpad::default_offset")]
+    pub x: f64,
+    #[serde(default = "Scratchpad::default_offset")]
+    pub y: f64,
+    #[serde(default = "Scratchpad::default_size")]
+    pub width: f64,
+    #[serde(default = "Scratchpad::default_size")]
+    pub height: f64,
+}
+
+impl Scratchpad {
+    pub fn command(&self) -> Command {
+        Command {
+            keybind: String::new(),
+            command: self.command.clone(),
+            shell: self.shell,
+            env: self.env.clone(),
+            cwd: self.cwd.clone(),
+        }
+    }
+
+    fn default_offset() -> f64 {
+        0.1
+    }
+
+    fn default_size() -> f64 {
+        0.8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Rule {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<String>,
+    pub window_type: Option<String>,
+    pub role: Option<String>,
+    pub tag: Option<String>,
+    pub floating: bool,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub fullscreen: bool,
+    pub ignore: bool,
+    pub focus: Option<bool>,
+    pub focus_stealing: Option<FocusStealing>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct WRule {
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub tag: Option<String>,
+    pub floating: bool,
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub width: Option<f64>,
+    pub height: Option<f64>,
+    pub fullscreen: bool,
+    pub focus: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LayoutConfig {
+    pub name: String,
+    #[serde(default)]
+    pub max_main: Option<u32>,
+    #[serde(default)]
+    pub ratio: Option<f32>,
+    #[serde(default)]
+    pub ratio_step: Option<f32>,
+    #[serde(default)]
+    pub outer_gaps: Option<u32>,
+    #[serde(default)]
+    pub inner_gaps: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TagConfig {
+    pub tag: String,
+    #[serde(default)]
+    pub layout: Option<String>,
+    #[serde(default)]
+    pub max_main: Option<u32>,
+    #[serde(default)]
+    pub ratio: Option<f32>,
+    #[serde(default)]
+    pub outer_gaps: Option<u32>,
+    #[serde(default)]
+    pub inner_gaps: Option<u32>,
+    #[serde(default)]
+    pub new_window: Option<NewWindow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NewWindow {
+    Main,
+    Stack,
+}
+
+// What an X client asking for focus with _NET_ACTIVE_WINDOW gets
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusStealing {
+    #[default]
+    Allow,
+    CurrentTag,
+    Urgent,
+    Deny,
+}
+
+// What a Wayland client asking to have one of its windows activated gets
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivationPolicy {
+    #[default]
+    Focus,
+    Urgent,
+    Ignore,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,19 +335,65 @@ pub struct Config {
     pub max_main: u32,
     pub normal_bordar: u32,
     pub focused_bordar: u32,
+    #[serde(default = "Config::default_urgent_border")]
+    pub urgent_border: u32,
     pub workspace_tags: Vec<String>,
     pub ratio: f32,
     pub ratio_step: f32,
     pub outer_gaps: u32,
     pub inner_gaps: u32,
     pub top_gaps: u32,
-    pub start_up: Vec<String>,
+    pub start_up: Vec<StartUp>,
+    #[serde(default)]
+    pub xstart_up: Vec<StartUp>,
+    #[serde(default)]
+    pub wstart_up: Vec<StartUp>,
+    #[serde(default)]
+    pub xdg_autostart: bool,
     pub commands: Vec<Command>,
     pub actions: Vec<Action>,
     pub xcommands: Vec<Command>,
     pub xactions: Vec<Action>,
     pub wcommands: Vec<Command>,
     pub wactions: Vec<Action>,
+    #[serde(default)]
+    pub keyboard: Keyboard,
+    #[serde(default)]
+    pub clipboard_persistence: bool,
+    #[serde(default)]
+    pub scratchpads: Vec<Scratchpad>,
+    #[serde(default)]
+    pub bar: Bar,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub wrules: Vec<WRule>,
+    #[serde(default)]
+    pub layouts: Vec<LayoutConfig>,
+    #[serde(default)]
+    pub wlayout: Option<String>,
+    #[serde(default)]
+    pub tag_settings: Vec<TagConfig>,
+    #[serde(default)]
+    pub mouse_bindings: Vec<MouseBinding>,
+    #[serde(default)]
+    pub key_chords: KeyChords,
+    #[serde(default)]
+    pub modes: Vec<Mode>,
+    #[serde(default)]
+    pub child_log_dir: Option<String>,
+    #[serde(default)]
+    pub i3_ipc: bool,
+    #[serde(default)]
+    pub wactivation: ActivationPolicy,
+    #[serde(default)]
+    pub focus_stealing: FocusStealing,
+}
+
+impl Config {
+    fn default_urgent_border() -> u32 {
+        0xfabd2fff
+    }
 }
 
 impl Default for Config {
@@ -45,8 +409,12 @@ impl Default for Config {
             ratio: 0.6,
             normal_bordar: 0x00000000,
             focused_bordar: 0xff0000ff,
+            urgent_border: Config::default_urgent_border(),
             workspace_tags,
             start_up: vec![],
+            xstart_up: vec![],
+            wstart_up: vec![],
+            xdg_autostart: false,
             ratio_step: 0.1,
             outer_gaps: 5,
             inner_gaps: 5,
@@ -55,10 +423,12 @@ impl Default for Config {
                 Command {
                     keybind: "M-S-Return".into(),
                     command: "alacritty".into(),
+                    ..Default::default()
                 },
                 Command {
                     keybind: "M-x".into(),
                     command: "firefox".into(),
+                    ..Default::default()
                 },
             ],
             actions: vec![
@@ -75,10 +445,12 @@ impl Default for Config {
                 Command {
                     keybind: "M-A-e".into(),
                     command: "emacs".into(),
+                    ..Default::default()
                 },
                 Command {
                     keybind: "M-C-v".into(),
                     command: "vim".into(),
+                    ..Default::default()
                 },
             ],
             xactions: vec![
@@ -147,9 +519,35 @@ impl Default for Config {
                 vec![Command {
                     keybind: "M-S-Return".into(),
                     command: "kitty".into(),
+                    ..Default::default()
                 }]
             },
             wactions: { vec![] },
+            keyboard: Keyboard::default(),
+            clipboard_persistence: false,
+            scratchpads: vec![],
+            bar: Bar::default(),
+            rules: vec![],
+            wrules: vec![],
+            layouts: vec![],
+            wlayout: None,
+            tag_settings: vec![],
+            mouse_bindings: vec![
+                MouseBinding {
+                    mousebind: "M-Left".into(),
+                    action: "Move".into(),
+                },
+                MouseBinding {
+                    mousebind: "M-Right".into(),
+                    action: "Resize".into(),
+                },
+            ],
+            key_chords: KeyChords::default(),
+            modes: vec![],
+            child_log_dir: None,
+            i3_ipc: false,
+            wactivation: ActivationPolicy::Focus,
+            focus_stealing: FocusStealing::Allow,
         };
         confy::store("sswm", Some("config"), cfg).unwrap();
         confy::load("sswm", Some("config")).unwrap()
@@ -0,0 +1,658 @@
+// Enough of the i3 IPC protocol for bars and scripts written against i3 or
+// sway, answered from the same requests as the sswm socket.
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    io::{self, ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tracing::{info, warn};
+
+use crate::ipc::{
+    call,
+    protocol::{Event, Output, Request, Tag, Window},
+    socket_path, subscribe, IpcRequest,
+};
+
+const MAGIC: &[u8] = b"i3-ipc";
+// Commands and subscriptions are tiny, a length above this is rejected before
+// anything is allocated for it
+const MAX_PAYLOAD: u32 = 1 << 20;
+
+const RUN_COMMAND: u32 = 0;
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_OUTPUTS: u32 = 3;
+const GET_TREE: u32 = 4;
+
+const EVENT_WORKSPACE: u32 = 1 << 31;
+const EVENT_WINDOW: u32 = 1 << 31 | 3;
+
+// Tags and outputs need container ids too, kept well clear of window ids
+const ROOT_ID: u64 = 1 << 40;
+const OUTPUT_ID: u64 = 2 << 40;
+const TAG_ID: u64 = 3 << 40;
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+// Lengths and types are in the host's byte order, like i3 does it
+fn read_message(reader: &mut impl Read) -> io::Result<(u32, Vec<u8>)> {
+    let mut header = [0u8; 14];
+    reader.read_exact(&mut header)?;
+    if &header[..6] != MAGIC {
+        return Err(invalid("not an i3-ipc message"));
+    }
+
+    let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    if len > MAX_PAYLOAD {
+        return Err(invalid("i3-ipc message too long"));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok((kind, payload))
+}
+
+fn write_message(writer: &Mutex<UnixStream>, kind: u32, payload: &Value) -> io::Result<()> {
+    let payload = serde_json::to_vec(payload)?;
+    let mut message = MAGIC.to_vec();
+    message.extend((payload.len() as u32).to_ne_bytes());
+    message.extend(kind.to_ne_bytes());
+    message.extend(payload);
+
+    writer.lock().unwrap().write_all(&message)
+}
+
+fn query<T, F>(dispatch: &F, request: Request) -> io::Result<T>
+where
+    T: DeserializeOwned,
+    F: Fn(IpcRequest),
+{
+    let response = call(dispatch, request);
+    if !response.success {
+        return Err(io::Error::new(
+            ErrorKind::Other,
+            response.error.unwrap_or_default(),
+        ));
+    }
+
+    serde_json::from_value(response.data.unwrap_or_default()).map_err(invalid)
+}
+
+fn error(e: impl ToString) -> Value {
+    json!({ "success": false, "error": e.to_string() })
+}
+
+fn rect(x: i32, y: i32, width: u32, height: u32) -> Value {
+    json!({ "x": x, "y": y, "width": width, "height": height })
+}
+
+fn output_rect(output: Option<&Output>) -> Value {
+    output.map_or_else(|| rect(0, 0, 0, 0), |o| rect(o.x, o.y, o.width, o.height))
+}
+
+// A workspace's number is whatever its name starts with, `1: www` is 1
+fn leading_number(name: &str) -> Option<i64> {
+    let end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+    name[..end].parse().ok()
+}
+
+// Named tags don't have a number, which i3 marks with -1
+fn tag_num(name: &str) -> i64 {
+    leading_number(name).unwrap_or(-1)
+}
+
+fn tag_output<'a>(tag: &Tag, outputs: &'a [Output]) -> Option<&'a Output> {
+    outputs.get(tag.output.unwrap_or(0))
+}
+
+// Like i3, only tags that are shown or hold windows exist as workspaces
+fn existing_tags(tags: &[Tag]) -> impl Iterator<Item = (usize, &Tag)> {
+    tags.iter()
+        .enumerate()
+        .filter(|(_, t)| t.visible || t.windows > 0)
+}
+
+fn workspace(index: usize, tag: &Tag, outputs: &[Output]) -> Value {
+    let output = tag_output(tag, outputs);
+
+    json!({
+        "id": TAG_ID + index as u64,
+        "num": tag_num(&tag.name),
+        "name": tag.name,
+        "visible": tag.visible,
+        "focused": tag.focused,
+        "urgent": tag.urgent,
+        "rect": output_rect(output),
+        "output": output.map(|o| o.name.as_str()).unwrap_or_default(),
+    })
+}
+
+fn con(window: &Window) -> Value {
+    json!({
+        "id": window.id,
+        "type": if window.floating { "floating_con" } else { "con" },
+        "name": window.title,
+        "focused": window.focused,
+        "urgent": window.urgent,
+        "window": window.id,
+        "app_id": window.class,
+        "window_properties": { "class": window.class, "title": window.title },
+        "fullscreen_mode": u8::from(window.fullscreen),
+        "layout": "none",
+        "rect": rect(0, 0, 0, 0),
+        "nodes": [],
+        "floating_nodes": [],
+        "marks": [],
+    })
+}
+
+// For windows that are gone by the time the event is sent
+fn con_stub(id: u64, title: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "con",
+        "name": title,
+        "window": id,
+        "rect": rect(0, 0, 0, 0),
+        "nodes": [],
+        "floating_nodes": [],
+    })
+}
+
+fn tree(tags: &[Tag], outputs: &[Output], windows: &[Window]) -> Value {
+    let outputs_json: Vec<Value> = outputs
+        .iter()
+        .map(|output| {
+            let workspaces: Vec<Value> = existing_tags(tags)
+                .filter(|(_, t)| t.output.unwrap_or(0) == output.index)
+                .map(|(i, tag)| {
+                    let on_tag = windows.iter().filter(|w| w.tag == tag.name);
+                    let (floating, tiled): (Vec<&Window>, Vec<&Window>) =
+                        on_tag.partition(|w| w.floating);
+
+                    let mut node = workspace(i, tag, outputs);
+                    node["type"] = "workspace".into();
+                    node["layout"] = tag.layout.clone().into();
+                    node["nodes"] = tiled.into_iter().map(con).collect();
+                    node["floating_nodes"] = floating.into_iter().map(con).collect();
+                    node
+                })
+                .collect();
+
+            json!({
+                "id": OUTPUT_ID + output.index as u64,
+                "type": "output",
+                "name": output.name,
+                "focused": false,
+                "rect": output_rect(Some(output)),
+                "nodes": workspaces,
+                "floating_nodes": [],
+            })
+        })
+        .collect();
+
+    json!({
+        "id": ROOT_ID,
+        "type": "root",
+        "name": "root",
+        "focused": false,
+        "rect": rect(0, 0, 0, 0),
+        "nodes": outputs_json,
+        "floating_nodes": [],
+    })
+}
+
+fn get_workspaces<F: Fn(IpcRequest)>(dispatch: &F) -> io::Result<Value> {
+    let tags: Vec<Tag> = query(dispatch, Request::GetTags)?;
+    let outputs: Vec<Output> = query(dispatch, Request::GetOutputs)?;
+
+    Ok(existing_tags(&tags)
+        .map(|(i, tag)| workspace(i, tag, &outputs))
+        .collect())
+}
+
+fn get_outputs<F: Fn(IpcRequest)>(dispatch: &F) -> io::Result<Value> {
+    let outputs: Vec<Output> = query(dispatch, Request::GetOutputs)?;
+
+    Ok(outputs
+        .iter()
+        .map(|o| {
+            json!({
+                "name": o.name,
+                "active": true,
+                "primary": o.index == 0,
+                "focused": o.focused,
+                "current_workspace": o.tag,
+                "rect": output_rect(Some(o)),
+            })
+        })
+        .collect())
+}
+
+fn get_tree<F: Fn(IpcRequest)>(dispatch: &F) -> io::Result<Value> {
+    let tags: Vec<Tag> = query(dispatch, Request::GetTags)?;
+    let outputs: Vec<Output> = query(dispatch, Request::GetOutputs)?;
+    let windows: Vec<Window> = query(dispatch, Request::GetWindows)?;
+
+    Ok(tree(&tags, &outputs, &windows))
+}
+
+// `number N` picks the first tag starting with N, or N itself if there is
+// none, the same as i3 falls back to creating workspace N
+fn tag_name(words: &[String], tags: &[String]) -> Option<String> {
+    match words {
+        [number, rest @ ..] if number == "number" && !rest.is_empty() => {
+            let wanted = rest.join(" ");
+            let Some(num) = leading_number(&wanted) else {
+                return Some(wanted);
+            };
+            let tag = tags.iter().find(|t| leading_number(t) == Some(num));
+            Some(tag.cloned().unwrap_or_else(|| num.to_string()))
+        }
+        [] => None,
+        _ => Some(words.join(" ")),
+    }
+}
+
+// The commands that map onto an sswm action, there is no criteria support
+fn command(cmd: &str, tags: &[String]) -> Option<Request> {
+    if let Some(rest) = cmd.strip_prefix("exec ") {
+        let rest = rest.trim_start();
+        let rest = rest.strip_prefix("--no-startup-id").unwrap_or(rest);
+        return Some(Request::RunCommand {
+            command: rest.trim().to_string(),
+            shell: true,
+        });
+    }
+
+    let args = shell_words::split(cmd).ok()?;
+    let words: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let action = match words.as_slice() {
+        ["kill"] => "Kill".to_string(),
+        ["exit"] => "Exit".to_string(),
+        ["focus", "right" | "down" | "next"] => "FocusNext".to_string(),
+        ["focus", "left" | "up" | "prev"] => "FocusPrevious".to_string(),
+        ["focus", "output", "right" | "down" | "next"] => "FocusNextScreen".to_string(),
+        ["focus", "output", "left" | "up" | "prev"] => "FocusPreviousScreen".to_string(),
+        ["move", "right" | "down"] => "SwapDown".to_string(),
+        ["move", "left" | "up"] => "SwapUp".to_string(),
+        ["move", "container" | "window", "to", "workspace", ..] => {
+            format!("MoveToTag {}", tag_name(&args[4..], tags)?)
+        }
+        ["move", "to", "workspace", ..] => {
+            format!("MoveToTag {}", tag_name(&args[3..], tags)?)
+        }
+        ["workspace", ..] => format!("FocusTag {}", tag_name(&args[1..], tags)?),
+        ["fullscreen"] | ["fullscreen", "toggle"] => "ToggleFullScreen".to_string(),
+        ["floating", "toggle" | "enable"] => "FloatFocused".to_string(),
+        ["layout", "toggle"] | ["layout", "toggle", "next" | "all"] => "NextLayout".to_string(),
+        ["layout", "toggle", "prev"] => "PreviousLayout".to_string(),
+        ["mode", "default"] => "ExitMode".to_string(),
+        ["mode", name] => format!("EnterMode {name}"),
+        _ => return None,
+    };
+
+    Some(Request::RunAction { action })
+}
+
+fn run_command<F: Fn(IpcRequest)>(dispatch: &F, payload: &str) -> Value {
+    let tags: Vec<String> = query::<Vec<Tag>, _>(dispatch, Request::GetTags)
+        .map(|tags| tags.into_iter().map(|t| t.name).collect())
+        .unwrap_or_default();
+
+    payload
+        .split(';')
+        .map(str::trim)
+        .filter(|cmd| !cmd.is_empty())
+        .map(|cmd| match command(cmd, &tags) {
+            Some(request) => {
+                let response = call(dispatch, request);
+                json!({ "success": response.success, "error": response.error })
+            }
+            None => error(format!("unsupported command: {cmd}")),
+        })
+        .collect()
+}
+
+// The window as it is now, if it is still around
+fn container<F: Fn(IpcRequest)>(dispatch: &F, id: u64, title: &str) -> io::Result<Value> {
+    let windows: Vec<Window> = query(dispatch, Request::GetWindows)?;
+    Ok(windows
+        .iter()
+        .find(|w| w.id == id)
+        .map_or_else(|| con_stub(id, title), con))
+}
+
+fn event_message<F: Fn(IpcRequest)>(
+    dispatch: &F,
+    event: Event,
+) -> io::Result<Option<(u32, Value)>> {
+    Ok(Some(match event {
+        Event::TagFocused { tag, .. } => {
+            let tags: Vec<Tag> = query(dispatch, Request::GetTags)?;
+            let outputs: Vec<Output> = query(dispatch, Request::GetOutputs)?;
+            let current = tags
+                .iter()
+                .enumerate()
+                .find(|(_, t)| t.name == tag)
+                .map(|(i, t)| workspace(i, t, &outputs));
+
+            (
+                EVENT_WORKSPACE,
+                json!({ "change": "focus", "current": current, "old": null }),
+            )
+        }
+        Event::WindowOpened { window } => (
+            EVENT_WINDOW,
+            json!({ "change": "new", "container": con(&window) }),
+        ),
+        Event::WindowClosed { id } => (
+            EVENT_WINDOW,
+            json!({ "change": "close", "container": con_stub(id, "") }),
+        ),
+        Event::WindowFocused {
+            window: Some(window),
+        } => (
+            EVENT_WINDOW,
+            json!({ "change": "focus", "container": con(&window) }),
+        ),
+        Event::WindowTitle { id, title } => (
+            EVENT_WINDOW,
+            json!({ "change": "title", "container": container(dispatch, id, &title)? }),
+        ),
+        Event::WindowUrgent { id, .. } => (
+            EVENT_WINDOW,
+            json!({ "change": "urgent", "container": container(dispatch, id, "")? }),
+        ),
+        _ => return Ok(None),
+    }))
+}
+
+fn subscribe_events<F>(dispatch: F, writer: Arc<Mutex<UnixStream>>, names: &[String])
+where
+    F: Fn(IpcRequest) + Send + 'static,
+{
+    let mut events = vec![];
+    if names.iter().any(|n| n == "workspace") {
+        events.push("tag_focused".to_string());
+    }
+    if names.iter().any(|n| n == "window") {
+        events.extend(
+            [
+                "window_opened",
+                "window_closed",
+                "window_focused",
+                "window_title",
+                "window_urgent",
+            ]
+            .map(String::from),
+        );
+    }
+    if events.is_empty() {
+        return;
+    }
+
+    let lines = subscribe(events);
+    thread::spawn(move || {
+        for line in lines {
+            let message = serde_json::from_str(&line)
+                .map_err(invalid)
+                .and_then(|event| event_message(&dispatch, event));
+
+            let result = match message {
+                Ok(Some((kind, payload))) => write_message(&writer, kind, &payload),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn serve<F>(stream: UnixStream, dispatch: F) -> io::Result<()>
+where
+    F: Fn(IpcRequest) + Clone + Send + 'static,
+{
+    let mut reader = stream.try_clone()?;
+    let writer = Arc::new(Mutex::new(stream));
+
+    loop {
+        let (kind, payload) = match read_message(&mut reader) {
+            Ok(message) => message,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if kind == SUBSCRIBE {
+            match serde_json::from_slice::<Vec<String>>(&payload) {
+                // Replied to first so no event can come before the reply
+                Ok(names) => {
+                    write_message(&writer, kind, &json!({ "success": true }))?;
+                    subscribe_events(dispatch.clone(), writer.clone(), &names);
+                }
+                Err(e) => write_message(&writer, kind, &error(e))?,
+            }
+            continue;
+        }
+
+        let reply = match kind {
+            RUN_COMMAND => Ok(run_command(&dispatch, &String::from_utf8_lossy(&payload))),
+            GET_WORKSPACES => get_workspaces(&dispatch),
+            GET_OUTPUTS => get_outputs(&dispatch),
+            GET_TREE => get_tree(&dispatch),
+            _ => Err(invalid("unsupported message type")),
+        };
+
+        write_message(&writer, kind, &reply.unwrap_or_else(error))?;
+    }
+}
+
+// i3 and sway clients find the socket through I3SOCK and SWAYSOCK
+pub fn start_server<F>(dispatch: F) -> io::Result<PathBuf>
+where
+    F: Fn(IpcRequest) + Clone + Send + 'static,
+{
+    let path = socket_path("sswm-i3");
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+
+    env::set_var("I3SOCK", &path);
+    env::set_var("SWAYSOCK", &path);
+    info!("i3 ipc socket at {}", path.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let dispatch = dispatch.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, dispatch) {
+                            warn!("i3 ipc client error: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("unable to accept an i3 ipc client: {e}"),
+            }
+        }
+    });
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(cmd: &str, tags: &[&str]) -> Option<String> {
+        let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
+        match command(cmd, &tags)? {
+            Request::RunAction { action } => Some(action),
+            other => panic!("expected an action, got {other:?}"),
+        }
+    }
+
+    fn message(kind: u32, payload: &[u8]) -> Vec<u8> {
+        let mut message = MAGIC.to_vec();
+        message.extend((payload.len() as u32).to_ne_bytes());
+        message.extend(kind.to_ne_bytes());
+        message.extend(payload);
+        message
+    }
+
+    #[test]
+    fn workspace_number_matches_leading_number() {
+        let tags = ["1: www", "2: code", "10: chat"];
+
+        assert_eq!(
+            action("workspace number 1", &tags).as_deref(),
+            Some("FocusTag 1: www")
+        );
+        assert_eq!(
+            action("workspace number 10", &tags).as_deref(),
+            Some("FocusTag 10: chat")
+        );
+        assert_eq!(
+            action("move container to workspace number 2", &tags).as_deref(),
+            Some("MoveToTag 2: code")
+        );
+    }
+
+    #[test]
+    fn workspace_number_falls_back_to_the_number() {
+        assert_eq!(
+            action("workspace number 3", &["1: www"]).as_deref(),
+            Some("FocusTag 3")
+        );
+        assert_eq!(
+            action("workspace number 4: mail", &[]).as_deref(),
+            Some("FocusTag 4")
+        );
+        assert_eq!(
+            action("workspace number www", &["1: www"]).as_deref(),
+            Some("FocusTag www")
+        );
+    }
+
+    #[test]
+    fn workspace_by_name() {
+        assert_eq!(
+            action("workspace 1: www", &["1: www"]).as_deref(),
+            Some("FocusTag 1: www")
+        );
+        assert_eq!(
+            action("move to workspace \"my tag\"", &[]).as_deref(),
+            Some("MoveToTag my tag")
+        );
+        assert_eq!(action("workspace", &[]), None);
+    }
+
+    #[test]
+    fn commands_map_to_actions() {
+        assert_eq!(action("kill", &[]).as_deref(), Some("Kill"));
+        assert_eq!(action("focus left", &[]).as_deref(), Some("FocusPrevious"));
+        assert_eq!(
+            action("focus output right", &[]).as_deref(),
+            Some("FocusNextScreen")
+        );
+        assert_eq!(action("move down", &[]).as_deref(), Some("SwapDown"));
+        assert_eq!(
+            action("fullscreen toggle", &[]).as_deref(),
+            Some("ToggleFullScreen")
+        );
+        assert_eq!(
+            action("layout toggle prev", &[]).as_deref(),
+            Some("PreviousLayout")
+        );
+        assert_eq!(
+            action("mode resize", &[]).as_deref(),
+            Some("EnterMode resize")
+        );
+        assert_eq!(action("mode default", &[]).as_deref(), Some("ExitMode"));
+        assert_eq!(action("split horizontal", &[]), None);
+    }
+
+    #[test]
+    fn exec_runs_through_the_shell() {
+        match command("exec --no-startup-id firefox --new-window", &[]) {
+            Some(Request::RunCommand { command, shell }) => {
+                assert_eq!(command, "firefox --new-window");
+                assert!(shell);
+            }
+            other => panic!("expected a command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn tag_numbers() {
+        assert_eq!(tag_num("1: www"), 1);
+        assert_eq!(tag_num("12"), 12);
+        assert_eq!(tag_num("www"), -1);
+    }
+
+    #[test]
+    fn read_message_reads_one_message() {
+        let mut bytes = message(GET_TREE, b"[]");
+        bytes.extend(message(RUN_COMMAND, b"kill"));
+        let mut reader = bytes.as_slice();
+
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            (GET_TREE, b"[]".to_vec())
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap(),
+            (RUN_COMMAND, b"kill".to_vec())
+        );
+        assert_eq!(
+            read_message(&mut reader).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn read_message_rejects_bad_magic() {
+        let mut bytes = message(GET_TREE, b"");
+        bytes[..6].copy_from_slice(b"i4-ipc");
+
+        assert_eq!(
+            read_message(&mut bytes.as_slice()).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn read_message_rejects_huge_payloads() {
+        let mut bytes = message(RUN_COMMAND, b"");
+        bytes[6..10].copy_from_slice(&u32::MAX.to_ne_bytes());
+
+        assert_eq!(
+            read_message(&mut bytes.as_slice()).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn read_message_needs_the_whole_payload() {
+        let bytes = message(RUN_COMMAND, b"kill");
+
+        assert_eq!(
+            read_message(&mut &bytes[..bytes.len() - 1])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,198 @@
+mod i3;
+pub mod protocol;
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+use crate::{
+    config::{Command, CommandLine},
+    ipc::protocol::{Event, Request, Response, SOCKET_ENV},
+};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+// Events queued for a subscriber that isn't reading them. It is dropped once
+// this many are waiting rather than letting the queue grow without bound.
+const SUBSCRIBER_QUEUE: usize = 1024;
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(vec![]);
+// Lets the backends skip building events nobody is listening to
+static SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+
+struct Subscriber {
+    events: Vec<String>,
+    sender: mpsc::SyncSender<Arc<str>>,
+}
+
+pub fn has_subscribers() -> bool {
+    SUBSCRIBED.load(Ordering::Relaxed)
+}
+
+// Called by the backends whenever something changes. The lines are only
+// queued here, so a client that stops reading can't hold up the backend, and
+// one that falls too far behind is dropped.
+pub fn emit(event: Event) {
+    if !has_subscribers() {
+        return;
+    }
+
+    let line: Arc<str> = match serde_json::to_string(&event) {
+        Ok(json) => format!("{json}\n").into(),
+        Err(e) => {
+            warn!("unable to serialize {}: {e}", event.name());
+            return;
+        }
+    };
+
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.retain(|s| {
+        let wanted = s.events.is_empty() || s.events.iter().any(|e| e == event.name());
+        !wanted || s.sender.try_send(line.clone()).is_ok()
+    });
+    SUBSCRIBED.store(!subscribers.is_empty(), Ordering::Relaxed);
+}
+
+fn subscribe(events: Vec<String>) -> mpsc::Receiver<Arc<str>> {
+    let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+    let mut subscribers = SUBSCRIBERS.lock().unwrap();
+    subscribers.push(Subscriber { events, sender });
+    SUBSCRIBED.store(true, Ordering::Relaxed);
+    receiver
+}
+
+fn stream_events(mut writer: UnixStream, events: Vec<String>) -> io::Result<()> {
+    // Ends once the client goes away, the next event then drops the
+    // subscriber. Being dropped for falling behind ends it too.
+    for line in subscribe(events) {
+        writer.write_all(line.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+// Hands the request to the backend and waits for it to answer
+fn call<F>(dispatch: &F, request: Request) -> Response
+where
+    F: Fn(IpcRequest),
+{
+    let (reply, receiver) = mpsc::channel();
+    dispatch(IpcRequest { request, reply });
+    receiver
+        .recv_timeout(REPLY_TIMEOUT)
+        .unwrap_or_else(|_| Response::error("no reply from sswm"))
+}
+
+// Builds the same kind of command as a `commands` entry in the config
+pub fn command(command: &str, shell: bool) -> Command {
+    Command {
+        command: CommandLine::Line(command.to_string()),
+        shell,
+        ..Default::default()
+    }
+}
+
+pub fn socket_path(name: &str) -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    dir.join(format!("{name}.{}.sock", std::process::id()))
+}
+
+// Removes the sockets again once the backend that started them is done
+#[derive(Debug, Default)]
+pub struct SocketGuard {
+    paths: Vec<PathBuf>,
+}
+
+impl SocketGuard {
+    pub fn remove(&mut self) {
+        for path in self.paths.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for SocketGuard {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+fn serve<F>(stream: UnixStream, dispatch: F) -> io::Result<()>
+where
+    F: Fn(IpcRequest),
+{
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe { events }) => {
+                serde_json::to_writer(&mut writer, &Response::done())?;
+                writer.write_all(b"\n")?;
+                return stream_events(writer, events);
+            }
+            Ok(request) => call(&dispatch, request),
+            Err(e) => Response::error(format!("invalid request: {e}")),
+        };
+
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+// Every client gets a thread of its own. Requests are handed to `dispatch`,
+// which has to get them over to whatever owns the window manager state and
+// reply from there. The i3 compatible socket is served the same way.
+pub fn start_server<F>(dispatch: F, i3_compat: bool) -> io::Result<SocketGuard>
+where
+    F: Fn(IpcRequest) + Clone + Send + 'static,
+{
+    let mut guard = SocketGuard::default();
+    if i3_compat {
+        guard.paths.push(i3::start_server(dispatch.clone())?);
+    }
+
+    let path = socket_path("sswm-ipc");
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    guard.paths.push(path.clone());
+
+    // Set before anything is spawned so every child can find the socket
+    env::set_var(SOCKET_ENV, &path);
+    info!("ipc socket at {}", path.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let dispatch = dispatch.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, dispatch) {
+                            warn!("ipc client error: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("unable to accept an ipc client: {e}"),
+            }
+        }
+    });
+
+    Ok(guard)
+}
@@ -0,0 +1,153 @@
+// Shared with the sswmctl binary, so this only depends on serde and serde_json.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const SOCKET_ENV: &str = "SSWM_SOCK";
+
+// One JSON object per line in each direction
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    GetTags,
+    GetWindows,
+    GetOutputs,
+    GetLayouts,
+    GetFocus,
+    // Any action that can be bound in the config, e.g. `ToggleScratchpad term`
+    RunAction {
+        action: String,
+    },
+    RunCommand {
+        command: String,
+        #[serde(default)]
+        shell: bool,
+    },
+    // Turns the connection into a stream of events, all of them when empty
+    Subscribe {
+        #[serde(default)]
+        events: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Response {
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn ok(data: impl Serialize) -> Self {
+        match serde_json::to_value(data) {
+            Ok(data) => Self {
+                success: true,
+                data: Some(data),
+                error: None,
+            },
+            Err(e) => Self::error(e.to_string()),
+        }
+    }
+
+    pub fn done() -> Self {
+        Self {
+            success: true,
+            data: None,
+            error: None,
+        }
+    }
+
+    pub fn error(error: impl Into<String>) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub focused: bool,
+    pub visible: bool,
+    pub windows: usize,
+    pub layout: String,
+    pub output: Option<usize>,
+    // Holds a window asking for attention
+    #[serde(default)]
+    pub urgent: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Window {
+    pub id: u64,
+    pub title: String,
+    // WM_CLASS class on X, app_id on Wayland
+    pub class: String,
+    pub tag: String,
+    pub focused: bool,
+    pub floating: bool,
+    pub fullscreen: bool,
+    #[serde(default)]
+    pub urgent: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Output {
+    pub index: usize,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub tag: String,
+    pub focused: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Layouts {
+    pub tag: String,
+    pub current: String,
+    pub available: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Focus {
+    pub tag: String,
+    pub output: usize,
+    pub window: Option<Window>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TagFocused { tag: String, output: usize },
+    WindowOpened { window: Window },
+    WindowClosed { id: u64 },
+    WindowFocused { window: Option<Window> },
+    WindowTitle { id: u64, title: String },
+    WindowUrgent { id: u64, urgent: bool },
+    // Only sent by the X backend, Wayland has one output and fixed layouts
+    LayoutChanged { tag: String, layout: String },
+    OutputAdded { output: Output },
+    OutputRemoved { index: usize },
+}
+
+impl Event {
+    // The name subscribers filter on, the same as the `event` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::TagFocused { .. } => "tag_focused",
+            Event::WindowOpened { .. } => "window_opened",
+            Event::WindowClosed { .. } => "window_closed",
+            Event::WindowFocused { .. } => "window_focused",
+            Event::WindowTitle { .. } => "window_title",
+            Event::WindowUrgent { .. } => "window_urgent",
+            Event::LayoutChanged { .. } => "layout_changed",
+            Event::OutputAdded { .. } => "output_added",
+            Event::OutputRemoved { .. } => "output_removed",
+        }
+    }
+}
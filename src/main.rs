@@ -3,15 +3,21 @@ use std::env::{self, args};
 use wayland::waywm::wayrun;
 use x::xwm::xrun;
 
+mod autostart;
 mod config;
+mod ipc;
+mod spawn;
+mod startup;
+mod supervisor;
 mod wayland;
 mod x;
 
 fn main() {
     let arg = args().nth(1);
     let cfg = get_config();
+    supervisor::init(&cfg);
     if arg.is_some() && arg.unwrap() == "way" {
-        wayrun().unwrap();
+        wayrun(cfg).unwrap();
     } else if env::var("DISPLAY").is_ok() {
         xrun(cfg).unwrap();
     }
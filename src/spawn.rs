@@ -0,0 +1,71 @@
+use std::{
+    env,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    process,
+};
+use tracing::warn;
+
+use crate::{
+    config::{Command, CommandLine},
+    supervisor,
+};
+
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn argv(cmd: &Command) -> io::Result<Vec<String>> {
+    let invalid = |e| io::Error::new(ErrorKind::InvalidInput, e);
+
+    let argv = match (&cmd.command, cmd.shell) {
+        (CommandLine::Line(line), true) => vec!["sh".into(), "-c".into(), line.clone()],
+        (CommandLine::Argv(argv), true) => {
+            vec!["sh".into(), "-c".into(), shell_words::join(argv)]
+        }
+        (CommandLine::Line(line), false) => {
+            shell_words::split(line).map_err(|e| invalid(e.to_string()))?
+        }
+        (CommandLine::Argv(argv), false) => argv.clone(),
+    };
+
+    if argv.is_empty() {
+        return Err(invalid("empty command".into()));
+    }
+
+    Ok(argv)
+}
+
+pub fn build(cmd: &Command) -> io::Result<process::Command> {
+    let argv = argv(cmd)?;
+    let mut command = process::Command::new(&argv[0]);
+    command.args(&argv[1..]).envs(&cmd.env);
+
+    if let Some(cwd) = &cmd.cwd {
+        command.current_dir(expand_home(cwd));
+    }
+
+    Ok(command)
+}
+
+pub fn name(cmd: &Command) -> &str {
+    let first = match &cmd.command {
+        CommandLine::Line(line) => line.split_whitespace().next(),
+        CommandLine::Argv(argv) => argv.first().map(String::as_str),
+    };
+
+    first.unwrap_or("command")
+}
+
+// Both backends spawn config commands through here, a command that can't be
+// started is logged rather than taking the window manager down with it.
+pub fn spawn(cmd: &Command) {
+    let result = build(cmd).and_then(|command| supervisor::spawn(name(cmd), command));
+
+    if let Err(e) = result {
+        warn!("unable to spawn {:?}: {e}", cmd.command);
+    }
+}
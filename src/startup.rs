@@ -0,0 +1,56 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+use tracing::warn;
+
+use crate::{
+    config::{Program, Restart, RunPolicy, StartUp},
+    spawn, supervisor,
+};
+
+// XDG_RUNTIME_DIR is emptied when the user logs out, so a marker in there
+// tells whether a `run: once` program already ran in this session.
+fn first_run(program: &Program) -> bool {
+    let Some(dir) = env::var_os("XDG_RUNTIME_DIR") else {
+        return true;
+    };
+
+    let mut hasher = DefaultHasher::new();
+    (&program.command, program.shell, &program.cwd).hash(&mut hasher);
+
+    let dir = PathBuf::from(dir).join("sswm");
+    let marker = dir.join(format!("started-{:x}", hasher.finish()));
+    if marker.exists() {
+        return false;
+    }
+
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::write(&marker, "")) {
+        warn!("unable to write {}: {e}", marker.display());
+    }
+
+    true
+}
+
+// Every entry is started on its own, one that fails doesn't keep the rest
+// from running.
+pub fn run_start_up<'a>(entries: impl IntoIterator<Item = &'a StartUp>) {
+    for entry in entries {
+        let program = entry.program();
+        if program.run == RunPolicy::Once && !first_run(&program) {
+            continue;
+        }
+
+        let cmd = program.command();
+        match program.restart {
+            Restart::Never => spawn::spawn(&cmd),
+            Restart::OnFailure => {
+                if let Err(e) = supervisor::spawn_restarting(&cmd) {
+                    warn!("unable to spawn {:?}: {e}", cmd.command);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    os::unix::net::UnixStream,
+    os::unix::{io::IntoRawFd, process::CommandExt},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{self, Config},
+    spawn::{self, expand_home},
+};
+
+static CHILDREN: Mutex<Vec<Supervised>> = Mutex::new(vec![]);
+static LOG_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+static SIGCHLD_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+pub fn init(cfg: &Config) {
+    let log_dir = cfg.child_log_dir.as_ref().map(|dir| {
+        let dir = expand_home(dir);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            warn!("unable to create {}: {e}", dir.display());
+        }
+        dir
+    });
+
+    let _ = LOG_DIR.set(log_dir);
+}
+
+fn log_file(name: &str) -> io::Result<Option<File>> {
+    let Some(Some(dir)) = LOG_DIR.get() else {
+        return Ok(None);
+    };
+
+    let name = name.rsplit('/').next().unwrap_or(name);
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{name}.log")))
+        .map(Some)
+}
+
+// Helpers that keep failing soon after starting are given up on after this
+// many restarts in a row
+const MAX_RESTARTS: u32 = 5;
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+struct Supervised {
+    name: String,
+    child: Child,
+    started: Instant,
+    // The command to run again if the child fails, with the failures so far
+    restart: Option<(config::Command, u32)>,
+}
+
+// Children get a session of their own so they outlive sswm and don't get its
+// terminal signals, and they start with the default signal mask whatever
+// the backend blocked for itself.
+fn start(name: &str, mut command: Command) -> io::Result<Child> {
+    if let Some(log) = log_file(name)? {
+        command.stdout(log.try_clone()?).stderr(log);
+    }
+    command.stdin(Stdio::null());
+
+    unsafe {
+        command.pre_exec(|| {
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::pthread_sigmask(libc::SIG_SETMASK, &set, std::ptr::null_mut());
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let child = command.spawn()?;
+    info!("spawned {name} ({})", child.id());
+    Ok(child)
+}
+
+pub fn spawn(name: &str, command: Command) -> io::Result<()> {
+    let child = start(name, command)?;
+    CHILDREN.lock().unwrap().push(Supervised {
+        name: name.to_string(),
+        child,
+        started: Instant::now(),
+        restart: None,
+    });
+    Ok(())
+}
+
+fn spawn_restarting_after(cmd: &config::Command, failures: u32) -> io::Result<()> {
+    let name = spawn::name(cmd);
+    let child = start(name, spawn::build(cmd)?)?;
+    CHILDREN.lock().unwrap().push(Supervised {
+        name: name.to_string(),
+        child,
+        started: Instant::now(),
+        restart: Some((cmd.clone(), failures)),
+    });
+    Ok(())
+}
+
+// Runs the command again whenever it exits with an error
+pub fn spawn_restarting(cmd: &config::Command) -> io::Result<()> {
+    spawn_restarting_after(cmd, 0)
+}
+
+// Waits on every child that has exited so none of them are left as zombies
+pub fn reap() {
+    let mut restarts = vec![];
+
+    CHILDREN.lock().unwrap().retain_mut(|s| {
+        let (name, id) = (&s.name, s.child.id());
+
+        match s.child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                info!("{name} ({id}) exited");
+                false
+            }
+            Ok(Some(status)) => {
+                warn!("{name} ({id}) exited with {status}");
+                let stable = s.started.elapsed() > STABLE_AFTER;
+                match s.restart.take() {
+                    Some((cmd, _)) if stable => restarts.push((cmd, 1)),
+                    Some((cmd, failures)) if failures < MAX_RESTARTS => {
+                        restarts.push((cmd, failures + 1))
+                    }
+                    Some(_) => warn!("not restarting {name}, it failed {MAX_RESTARTS} times"),
+                    None => {}
+                }
+                false
+            }
+            Ok(None) => true,
+            Err(e) => {
+                error!("unable to wait for {name} ({id}): {e}");
+                false
+            }
+        }
+    });
+
+    // Spawning takes the lock again, so only once it has been released
+    for (cmd, failures) in restarts {
+        info!(
+            "restarting {} ({failures}/{MAX_RESTARTS})",
+            spawn::name(&cmd)
+        );
+        if let Err(e) = spawn_restarting_after(&cmd, failures) {
+            warn!("unable to restart {:?}: {e}", cmd.command);
+        }
+    }
+}
+
+extern "C" fn on_sigchld(_: libc::c_int) {
+    let fd = SIGCHLD_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // Only async-signal-safe calls in here, the reaping happens in a thread
+        unsafe { libc::write(fd, [0u8].as_ptr().cast(), 1) };
+    }
+}
+
+// Penrose has no event loop to plug a signal source into, so on X the signal
+// handler wakes up a thread through a socket pair instead. Children that
+// exited while SIGCHLD was still ignored are picked up straight away.
+pub fn watch_sigchld() -> io::Result<()> {
+    let (mut reader, writer) = UnixStream::pair()?;
+    writer.set_nonblocking(true)?;
+    SIGCHLD_PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigchld as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    thread::spawn(move || {
+        reap();
+        let mut buf = [0; 64];
+        while reader.read(&mut buf).is_ok_and(|n| n > 0) {
+            reap();
+        }
+    });
+
+    Ok(())
+}
@@ -1,6 +1,268 @@
-#[derive(PartialEq)]
+use crate::{
+    config::{self, Config, KeyChords},
+    supervisor,
+};
+use smithay::input::keyboard::{keysyms, xkb, ModifiersState};
+use std::{
+    collections::HashMap,
+    process::Command,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+#[derive(PartialEq, Clone)]
 pub enum Action {
     WorkspaceSetActive(usize),
     WindowSetWorkspace(usize),
-    Spawn(String),
+    FocusTag(String),
+    MoveToTag(String),
+    FocusUrgent,
+    Spawn(config::Command),
+    SwitchKeyboardLayout,
+    EnterMode(String),
+    ExitMode,
+    // Keeps a key away from clients without doing anything
+    Ignore,
+}
+
+#[derive(PartialEq, Clone, Copy, Default)]
+pub struct Modifiers {
+    pub logo: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+    pub shift: bool,
+}
+
+impl From<&ModifiersState> for Modifiers {
+    fn from(state: &ModifiersState) -> Self {
+        Self {
+            logo: state.logo,
+            alt: state.alt,
+            ctrl: state.ctrl,
+            shift: state.shift,
+        }
+    }
+}
+
+impl Modifiers {
+    fn prefix(&self) -> String {
+        [
+            (self.logo, "M-"),
+            (self.alt, "A-"),
+            (self.ctrl, "C-"),
+            (self.shift, "S-"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, p)| *p)
+        .collect()
+    }
+}
+
+pub type Key = (Modifiers, u32);
+
+// A single combo or a sequence of them, e.g. `M-a t`
+#[derive(Clone)]
+pub struct KeyBinding {
+    pub keys: Vec<Key>,
+    pub action: Action,
+}
+
+// Same notation as penrose on X: `M-S-Return`, `A-x`, `C-bracketleft`...
+pub fn parse_keybind(keybind: &str) -> Option<(Modifiers, u32)> {
+    let mut modifiers = Modifiers::default();
+    let mut parts = keybind.split('-').collect::<Vec<_>>();
+    let key = parts.pop()?;
+
+    for part in parts {
+        match part {
+            "M" => modifiers.logo = true,
+            "A" => modifiers.alt = true,
+            "C" => modifiers.ctrl = true,
+            "S" => modifiers.shift = true,
+            _ => return None,
+        }
+    }
+
+    match xkb::keysym_from_name(key, xkb::KEYSYM_NO_FLAGS) {
+        keysyms::KEY_NoSymbol => None,
+        keysym => Some((modifiers, keysym)),
+    }
+}
+
+pub fn parse_key_sequence(keybind: &str) -> Option<Vec<Key>> {
+    let keys = keybind
+        .split_whitespace()
+        .map(parse_keybind)
+        .collect::<Option<Vec<_>>>()?;
+
+    (!keys.is_empty()).then_some(keys)
+}
+
+fn is_modifier(keysym: u32) -> bool {
+    (keysyms::KEY_Shift_L..=keysyms::KEY_Hyper_R).contains(&keysym)
+        || keysym == keysyms::KEY_ISO_Level3_Shift
+}
+
+pub enum ChordStep {
+    Run(Action),
+    // Part of a sequence, or a key that aborted one, nothing to do
+    Swallow,
+    Unbound,
+}
+
+pub struct Chord {
+    pending: Vec<Key>,
+    last_key: Instant,
+    timeout: Duration,
+    hint_command: Option<String>,
+}
+
+impl Chord {
+    pub fn new(cfg: &KeyChords) -> Self {
+        Self {
+            pending: vec![],
+            last_key: Instant::now(),
+            timeout: Duration::from_millis(cfg.timeout),
+            hint_command: cfg.hint_command.clone(),
+        }
+    }
+
+    // Bindings are checked in order and the first complete match wins, so a
+    // single combo shadows sequences starting with it.
+    pub fn press(
+        &mut self,
+        bindings: &[KeyBinding],
+        modifiers: Modifiers,
+        keysyms: &[u32],
+    ) -> ChordStep {
+        if self.last_key.elapsed() > self.timeout {
+            self.pending.clear();
+        }
+        self.last_key = Instant::now();
+
+        let in_sequence = !self.pending.is_empty();
+        if in_sequence && keysyms.iter().all(|k| is_modifier(*k)) {
+            return ChordStep::Swallow;
+        }
+
+        for keysym in keysyms {
+            let mut keys = self.pending.clone();
+            keys.push((modifiers, *keysym));
+
+            if let Some(binding) = bindings.iter().find(|b| b.keys == keys) {
+                self.pending.clear();
+                return ChordStep::Run(binding.action.clone());
+            }
+
+            if bindings.iter().any(|b| b.keys.starts_with(&keys)) {
+                self.pending = keys;
+                self.show_hint();
+                return ChordStep::Swallow;
+            }
+        }
+
+        // An unbound key in the middle of a sequence aborts it
+        self.pending.clear();
+        if in_sequence {
+            ChordStep::Swallow
+        } else {
+            ChordStep::Unbound
+        }
+    }
+
+    fn show_hint(&self) {
+        let Some(hint) = &self.hint_command else {
+            return;
+        };
+
+        let prefix = self
+            .pending
+            .iter()
+            .map(|(modifiers, keysym)| {
+                format!("{}{}", modifiers.prefix(), xkb::keysym_get_name(*keysym))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // The pending keys are passed as an argument rather than spliced in
+        let mut command = Command::new("sh");
+        command.args(["-c", &format!("{hint} \"$1\""), "sh", &prefix]);
+        if let Err(e) = supervisor::spawn("hint", command) {
+            warn!("unable to run the key chord hint: {e}");
+        }
+    }
+}
+
+pub fn match_actions(action_str: &str) -> Option<Action> {
+    let (action, arg) = action_str
+        .trim()
+        .split_once(' ')
+        .map(|(action, arg)| (action, arg.trim()))
+        .unwrap_or((action_str.trim(), ""));
+
+    match action.to_lowercase().as_str() {
+        "switchkeyboardlayout" => Some(Action::SwitchKeyboardLayout),
+        "focustag" => Some(Action::FocusTag(arg.to_string())),
+        "movetotag" => Some(Action::MoveToTag(arg.to_string())),
+        "focusurgent" => Some(Action::FocusUrgent),
+        "entermode" => Some(Action::EnterMode(arg.to_string())),
+        "exitmode" => Some(Action::ExitMode),
+        _ => None,
+    }
+}
+
+fn collect_bindings<'a>(
+    commands: impl Iterator<Item = &'a config::Command>,
+    actions: impl Iterator<Item = &'a config::Action>,
+) -> Vec<KeyBinding> {
+    let mut key_bindings = vec![];
+
+    for i in commands {
+        if let Some(keys) = parse_key_sequence(&i.keybind) {
+            key_bindings.push(KeyBinding {
+                keys,
+                action: Action::Spawn(i.clone()),
+            });
+        }
+    }
+
+    for i in actions {
+        if let (Some(keys), Some(action)) =
+            (parse_key_sequence(&i.keybind), match_actions(&i.action))
+        {
+            key_bindings.push(KeyBinding { keys, action });
+        }
+    }
+
+    // Later entries win, so the w-prefixed lists override the shared ones
+    key_bindings.reverse();
+    key_bindings
+}
+
+pub fn key_bindings(cfg: &Config) -> Vec<KeyBinding> {
+    collect_bindings(
+        cfg.commands.iter().chain(cfg.wcommands.iter()),
+        cfg.actions.iter().chain(cfg.wactions.iter()),
+    )
+}
+
+// Escape leaves every mode unless the mode binds it to something else
+pub fn mode_bindings(cfg: &Config) -> HashMap<String, Vec<KeyBinding>> {
+    cfg.modes
+        .iter()
+        .map(|mode| {
+            let mut bindings = collect_bindings(mode.commands.iter(), mode.actions.iter());
+            let escape = vec![(Modifiers::default(), keysyms::KEY_Escape)];
+
+            if !bindings.iter().any(|b| b.keys == escape) {
+                bindings.push(KeyBinding {
+                    keys: escape,
+                    action: Action::ExitMode,
+                });
+            }
+
+            (mode.name.clone(), bindings)
+        })
+        .collect()
 }
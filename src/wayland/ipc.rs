@@ -0,0 +1,226 @@
+use crate::{
+    ipc::{
+        self,
+        protocol::{Event, Focus, Layouts, Output, Request, Response, Tag, Window as IpcWindow},
+    },
+    spawn::spawn,
+    wayland::{
+        input::match_actions,
+        state::{app_id_and_title, State},
+        waywm::run_action,
+    },
+};
+use smithay::{desktop::Window, reexports::wayland_server::protocol::wl_surface::WlSurface};
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_WINDOW_ID: AtomicU64 = AtomicU64::new(1);
+
+// Wayland has no global window ids, so every window is numbered the first
+// time a client asks about it
+struct WindowId(u64);
+
+fn window_id(window: &Window) -> u64 {
+    window
+        .user_data()
+        .insert_if_missing(|| WindowId(NEXT_WINDOW_ID.fetch_add(1, Ordering::Relaxed)));
+    window.user_data().get::<WindowId>().unwrap().0
+}
+
+fn window(
+    state: &State,
+    workspace: usize,
+    window: &Window,
+    floating: bool,
+    fullscreen: bool,
+) -> IpcWindow {
+    let (app_id, title) = app_id_and_title(window);
+    let workspaces = &state.workspaces;
+
+    IpcWindow {
+        id: window_id(window),
+        title: title.unwrap_or_default(),
+        class: app_id.unwrap_or_default(),
+        tag: workspaces.name(workspace).to_string(),
+        focused: workspace == workspaces.active() && workspaces.active_window() == Some(window),
+        floating,
+        fullscreen,
+        urgent: workspaces.is_urgent(window),
+    }
+}
+
+fn tags(state: &State) -> Vec<Tag> {
+    let workspaces = &state.workspaces;
+
+    (0..workspaces.count())
+        .map(|i| {
+            let active = i == workspaces.active();
+            Tag {
+                name: workspaces.name(i).to_string(),
+                focused: active,
+                visible: active,
+                windows: workspaces.windows(i).count(),
+                layout: workspaces.layout_name(i).to_string(),
+                output: active.then_some(0),
+                urgent: workspaces.has_urgent(i),
+            }
+        })
+        .collect()
+}
+
+fn windows(state: &State) -> Vec<IpcWindow> {
+    (0..state.workspaces.count())
+        .flat_map(|i| {
+            state
+                .workspaces
+                .windows(i)
+                .map(move |(w, floating, fullscreen)| window(state, i, w, floating, fullscreen))
+        })
+        .collect()
+}
+
+// Every output shows the active workspace for now
+fn outputs(state: &State) -> Vec<Output> {
+    let tag = state.workspaces.name(state.workspaces.active());
+
+    state
+        .space
+        .outputs()
+        .enumerate()
+        .filter_map(|(index, output)| {
+            let geometry = state.space.output_geometry(output)?;
+            Some(Output {
+                index,
+                name: output.name(),
+                x: geometry.loc.x,
+                y: geometry.loc.y,
+                width: geometry.size.w as u32,
+                height: geometry.size.h as u32,
+                tag: tag.to_string(),
+                focused: index == 0,
+            })
+        })
+        .collect()
+}
+
+fn layouts(state: &State) -> Layouts {
+    let active = state.workspaces.active();
+    let current = state.workspaces.layout_name(active).to_string();
+
+    Layouts {
+        tag: state.workspaces.name(active).to_string(),
+        available: vec![current.clone()],
+        current,
+    }
+}
+
+fn focus(state: &State) -> Focus {
+    let active = state.workspaces.active();
+
+    Focus {
+        tag: state.workspaces.name(active).to_string(),
+        output: 0,
+        // Looked up like the events do, so floating and fullscreen agree with GetWindows
+        window: state
+            .workspaces
+            .active_window()
+            .and_then(|w| event_window(state, w.toplevel().wl_surface())),
+    }
+}
+
+pub fn handle_request(state: &mut State, request: &Request) -> Response {
+    match request {
+        Request::GetTags => Response::ok(tags(state)),
+        Request::GetWindows => Response::ok(windows(state)),
+        Request::GetOutputs => Response::ok(outputs(state)),
+        Request::GetLayouts => Response::ok(layouts(state)),
+        Request::GetFocus => Response::ok(focus(state)),
+        Request::RunAction { action } => match match_actions(action) {
+            Some(action) => {
+                let seat = state.seat.clone();
+                run_action(state, &seat, action);
+                Response::done()
+            }
+            None => Response::error(format!("unknown action: {action}")),
+        },
+        Request::RunCommand { command, shell } => {
+            spawn(&ipc::command(command, *shell));
+            Response::done()
+        }
+        // Handled by the server itself
+        Request::Subscribe { .. } => Response::error("unexpected subscribe"),
+    }
+}
+
+fn event_window(state: &State, surface: &WlSurface) -> Option<IpcWindow> {
+    let (workspace, w, floating, fullscreen) = state.workspaces.find_window(surface)?;
+    Some(window(state, workspace, w, floating, fullscreen))
+}
+
+pub fn window_opened(state: &State, surface: &WlSurface) {
+    if !ipc::has_subscribers() {
+        return;
+    }
+
+    if let Some(window) = event_window(state, surface) {
+        ipc::emit(Event::WindowOpened { window });
+    }
+}
+
+pub fn window_closed(window: &Window) {
+    ipc::emit(Event::WindowClosed {
+        id: window_id(window),
+    });
+}
+
+pub fn window_focused(state: &State, surface: Option<&WlSurface>) {
+    if !ipc::has_subscribers() {
+        return;
+    }
+
+    ipc::emit(Event::WindowFocused {
+        window: surface.and_then(|s| event_window(state, s)),
+    });
+}
+
+pub fn window_urgent(window: &Window, urgent: bool) {
+    ipc::emit(Event::WindowUrgent {
+        id: window_id(window),
+        urgent,
+    });
+}
+
+pub fn tag_focused(state: &State) {
+    ipc::emit(Event::TagFocused {
+        tag: state.workspaces.name(state.workspaces.active()).to_string(),
+        output: 0,
+    });
+}
+
+struct LastTitle(RefCell<Option<String>>);
+
+// Titles arrive with the next commit, which is when they are compared
+pub fn title_committed(state: &State, surface: &WlSurface) {
+    if !ipc::has_subscribers() {
+        return;
+    }
+
+    let Some((_, window, ..)) = state.workspaces.find_window(surface) else {
+        return;
+    };
+    let (_, title) = app_id_and_title(window);
+
+    let user_data = window.user_data();
+    user_data.insert_if_missing(|| LastTitle(RefCell::new(title.clone())));
+    let last = &user_data.get::<LastTitle>().unwrap().0;
+
+    if *last.borrow() != title {
+        last.replace(title.clone());
+        ipc::emit(Event::WindowTitle {
+            id: window_id(window),
+            title: title.unwrap_or_default(),
+        });
+    }
+}
@@ -1,6 +1,9 @@
 pub mod data;
 pub mod element;
 pub mod input;
+pub mod ipc;
+pub mod rules;
+pub mod selection;
 pub mod state;
 pub mod waywm;
 pub mod workspace;
@@ -0,0 +1,97 @@
+use crate::{config::WRule, wayland::workspace::FloatGeometry};
+use regex::Regex;
+use tracing::warn;
+
+pub struct WindowRule {
+    app_id: Option<String>,
+    title: Option<Regex>,
+    workspace: Option<usize>,
+    rule: WRule,
+}
+
+pub struct Placement {
+    pub workspace: Option<usize>,
+    pub floating: Option<FloatGeometry>,
+    pub fullscreen: bool,
+    pub focus: bool,
+}
+
+// Tags are looked up in `workspace_tags` so rules name workspaces the same
+// way on both backends.
+pub fn window_rules(rules: &[WRule], tags: &[String]) -> Vec<WindowRule> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let title = match rule.title.as_deref().map(Regex::new) {
+                Some(Ok(title)) => Some(title),
+                Some(Err(e)) => {
+                    warn!("skipping rule with invalid title regex: {e}");
+                    return None;
+                }
+                None => None,
+            };
+
+            Some(WindowRule {
+                app_id: rule.app_id.clone(),
+                title,
+                workspace: rule
+                    .tag
+                    .as_ref()
+                    .and_then(|tag| tags.iter().position(|t| t == tag)),
+                rule: rule.clone(),
+            })
+        })
+        .collect()
+}
+
+pub fn placement(rules: &[WindowRule], app_id: Option<&str>, title: Option<&str>) -> Placement {
+    let mut placement = Placement {
+        workspace: None,
+        floating: None,
+        fullscreen: false,
+        focus: true,
+    };
+
+    for WindowRule {
+        app_id: rule_app_id,
+        title: rule_title,
+        workspace,
+        rule,
+    } in rules
+    {
+        if rule_app_id.is_none() && rule_title.is_none() {
+            continue;
+        }
+
+        if rule_app_id.is_some() && rule_app_id.as_deref() != app_id {
+            continue;
+        }
+
+        if let Some(rule_title) = rule_title {
+            if !title.is_some_and(|title| rule_title.is_match(title)) {
+                continue;
+            }
+        }
+
+        if workspace.is_some() {
+            placement.workspace = *workspace;
+        }
+
+        if rule.floating {
+            placement.floating = Some(FloatGeometry {
+                x: rule.x.unwrap_or(0.25),
+                y: rule.y.unwrap_or(0.25),
+                w: rule.width.unwrap_or(0.5),
+                h: rule.height.unwrap_or(0.5),
+            });
+        }
+
+        placement.fullscreen |= rule.fullscreen;
+
+        if let Some(focus) = rule.focus {
+            placement.focus = focus;
+        }
+    }
+
+    placement
+}
@@ -0,0 +1,117 @@
+use smithay::reexports::calloop::channel::Sender;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Write},
+    os::{fd::OwnedFd, unix::net::UnixStream},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+// A source that keeps writing would otherwise grow the compositor without
+// bound, so types over the limit, or past the total, aren't kept
+const MAX_TYPE_BYTES: u64 = 16 << 20;
+const MAX_SELECTION_BYTES: u64 = 64 << 20;
+
+pub type SelectionData = Arc<HashMap<String, Vec<u8>>>;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SelectionTarget {
+    Clipboard,
+    Primary,
+}
+
+pub struct KeptSelection {
+    pub target: SelectionTarget,
+    pub generation: u64,
+    pub data: HashMap<String, Vec<u8>>,
+}
+
+// Counts the selections clients have set or cleared, so a copy that is still being read
+// when the next one comes in can be told apart and dropped.
+#[derive(Default)]
+pub struct SelectionGenerations {
+    clipboard: u64,
+    primary: u64,
+}
+
+impl SelectionGenerations {
+    fn get_mut(&mut self, target: SelectionTarget) -> &mut u64 {
+        match target {
+            SelectionTarget::Clipboard => &mut self.clipboard,
+            SelectionTarget::Primary => &mut self.primary,
+        }
+    }
+
+    pub fn next(&mut self, target: SelectionTarget) -> u64 {
+        let generation = self.get_mut(target);
+        *generation += 1;
+        *generation
+    }
+
+    pub fn is_current(&self, kept: &KeptSelection) -> bool {
+        let current = match kept.target {
+            SelectionTarget::Clipboard => self.clipboard,
+            SelectionTarget::Primary => self.primary,
+        };
+        kept.generation == current
+    }
+}
+
+// Reads every offered mime type off the event loop thread, since the source
+// client only writes once we are back to dispatching its requests.
+pub fn keep_selection<F>(
+    target: SelectionTarget,
+    generation: u64,
+    mime_types: Vec<String>,
+    mut request: F,
+    sender: Sender<KeptSelection>,
+) where
+    F: FnMut(String, OwnedFd) -> bool,
+{
+    let mut streams = vec![];
+
+    for mime_type in mime_types {
+        if let Ok((read, write)) = UnixStream::pair() {
+            if request(mime_type.clone(), write.into()) {
+                streams.push((mime_type, read));
+            }
+        }
+    }
+
+    if streams.is_empty() {
+        return;
+    }
+
+    thread::spawn(move || {
+        let mut data = HashMap::new();
+        let mut remaining = MAX_SELECTION_BYTES;
+
+        for (mime_type, stream) in streams {
+            let limit = remaining.min(MAX_TYPE_BYTES);
+            let mut buf = vec![];
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+            if stream.take(limit + 1).read_to_end(&mut buf).is_ok() && buf.len() as u64 <= limit {
+                remaining -= buf.len() as u64;
+                data.insert(mime_type, buf);
+            }
+        }
+
+        if !data.is_empty() {
+            let _ = sender.send(KeptSelection {
+                target,
+                generation,
+                data,
+            });
+        }
+    });
+}
+
+pub fn send_kept_selection(data: &SelectionData, mime_type: &str, fd: OwnedFd) {
+    if let Some(buf) = data.get(mime_type).cloned() {
+        thread::spawn(move || {
+            let _ = File::from(fd).write_all(&buf);
+        });
+    }
+}
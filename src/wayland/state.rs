@@ -1,37 +1,92 @@
-use crate::wayland::{data::ClientData, workspace::Workspaces};
+use crate::{
+    config::ActivationPolicy,
+    wayland::{
+        data::ClientData,
+        input::{Chord, KeyBinding},
+        ipc,
+        rules::{placement, WindowRule},
+        selection::{
+            keep_selection, send_kept_selection, KeptSelection, SelectionData,
+            SelectionGenerations, SelectionTarget,
+        },
+        workspace::Workspaces,
+    },
+};
 use smithay::{
     backend::renderer::utils::on_commit_buffer_handler,
-    delegate_compositor, delegate_data_device, delegate_output, delegate_seat, delegate_shm,
-    delegate_xdg_shell,
-    desktop::{Space, Window},
-    input::{pointer::CursorImageStatus, SeatHandler, SeatState},
+    delegate_compositor, delegate_data_device, delegate_output, delegate_primary_selection,
+    delegate_seat, delegate_shm, delegate_xdg_activation, delegate_xdg_shell,
+    desktop::{Space, Window, WindowSurfaceType},
+    input::{pointer::CursorImageStatus, Seat, SeatHandler, SeatState},
     reexports::{
-        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        calloop::channel::Sender,
+        wayland_protocols::{
+            wp::primary_selection::zv1::server::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+            xdg::shell::server::xdg_toplevel,
+        },
         wayland_server::{
-            protocol::{wl_buffer, wl_seat, wl_surface::WlSurface},
-            Client,
+            protocol::{wl_buffer, wl_data_source::WlDataSource, wl_seat, wl_surface::WlSurface},
+            Client, DisplayHandle, Resource,
         },
     },
-    utils::{Clock, Logical, Monotonic, Point, Serial},
+    utils::{Clock, Logical, Monotonic, Point, Serial, SERIAL_COUNTER},
     wayland::{
         buffer::BufferHandler,
-        compositor::{with_states, CompositorClientState, CompositorHandler, CompositorState},
+        compositor::{
+            with_states, CompositorClientState, CompositorHandler, CompositorState,
+            SurfaceAttributes,
+        },
         data_device::{
+            self, request_data_device_client_selection, set_data_device_focus,
             ClientDndGrabHandler, DataDeviceHandler, DataDeviceState, ServerDndGrabHandler,
         },
         output::OutputManagerState,
+        primary_selection::{
+            self, request_primary_client_selection, set_primary_focus, PrimarySelectionHandler,
+            PrimarySelectionState,
+        },
         shell::xdg::{
             PopupSurface, PositionerState, ToplevelSurface, XdgShellHandler, XdgShellState,
             XdgToplevelSurfaceData,
         },
         shm::{ShmHandler, ShmState},
+        xdg_activation::{
+            XdgActivationHandler, XdgActivationState, XdgActivationToken, XdgActivationTokenData,
+        },
     },
 };
+use std::{
+    collections::HashMap,
+    os::fd::OwnedFd,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+// Activation tokens are only honoured for a little while after the input
+// they were made for
+const TOKEN_LIFETIME: Duration = Duration::from_secs(10);
+
+pub fn app_id_and_title(window: &Window) -> (Option<String>, Option<String>) {
+    with_states(window.toplevel().wl_surface(), |states| {
+        let data = states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .unwrap()
+            .lock()
+            .unwrap();
+        (data.app_id.clone(), data.title.clone())
+    })
+}
 
 pub struct State {
     pub clock: Clock<Monotonic>,
+    pub display_handle: DisplayHandle,
     pub compositor_state: CompositorState,
     pub data_device_state: DataDeviceState,
+    pub primary_selection_state: PrimarySelectionState,
+    pub clipboard_keeper: Option<Sender<KeptSelection>>,
+    pub selection_generations: SelectionGenerations,
+    pub seat: Seat<Self>,
     pub seat_state: SeatState<Self>,
     pub shm_state: ShmState,
     pub space: Space<Window>,
@@ -39,7 +94,161 @@ pub struct State {
     pub pointer_location: Point<f64, Logical>,
     pub output_manager_state: OutputManagerState,
     pub xdg_shell_state: XdgShellState,
+    pub xdg_activation_state: XdgActivationState,
+    pub activation_policy: ActivationPolicy,
+    // Tokens handed to spawned commands, which have no input serial of their own
+    pub spawn_tokens: Vec<(String, Instant)>,
     pub workspaces: Workspaces,
+    pub key_bindings: Vec<KeyBinding>,
+    pub chord: Chord,
+    pub mode: Option<String>,
+    pub mode_bindings: HashMap<String, Vec<KeyBinding>>,
+    pub dnd_icon: Option<DndIcon>,
+    pub window_rules: Vec<WindowRule>,
+    pub pending_windows: Vec<Window>,
+    // Activation requests for windows that weren't placed yet, and whether
+    // they passed the checks
+    pub pending_activations: Vec<(WlSurface, bool)>,
+}
+
+// The surface dragged along with the pointer. It starts with its top left
+// corner on the pointer and clients move it by attaching buffers with an offset.
+pub struct DndIcon {
+    pub surface: WlSurface,
+    pub offset: Point<i32, Logical>,
+}
+
+impl State {
+    pub fn surface_under(
+        &self,
+        pos: Point<f64, Logical>,
+    ) -> Option<(WlSurface, Point<i32, Logical>)> {
+        self.space
+            .element_under(pos)
+            .and_then(|(window, location)| {
+                window
+                    .surface_under(pos - location.to_f64(), WindowSurfaceType::ALL)
+                    .map(|(s, p)| (s, p + location))
+            })
+    }
+
+    // Called on the first commit of a toplevel, by which point clients have
+    // set their app_id and title for the rules to match against.
+    fn place_window(&mut self, window: Window) {
+        let surface = window.toplevel().wl_surface().clone();
+        let (app_id, title) = app_id_and_title(&window);
+
+        let placement = placement(&self.window_rules, app_id.as_deref(), title.as_deref());
+        let workspace = placement
+            .workspace
+            .filter(|w| *w < self.workspaces.count())
+            .unwrap_or(self.workspaces.active());
+
+        if placement.fullscreen {
+            self.workspaces.insert_fullscreen(workspace, window.clone());
+        } else if let Some(geometry) = placement.floating {
+            self.workspaces
+                .insert_floating(workspace, window.clone(), geometry);
+        } else {
+            self.workspaces.insert_window(workspace, window.clone());
+        }
+
+        self.workspaces.refresh_geometry(&mut self.space);
+
+        // Windows placed on a hidden workspace aren't configured by the
+        // refresh, but still need their initial configure to map at all
+        window.toplevel().send_pending_configure();
+
+        ipc::window_opened(self, &surface);
+
+        if placement.focus && workspace == self.workspaces.active() {
+            self.focus_window(window.clone());
+        }
+
+        if let Some(i) = self
+            .pending_activations
+            .iter()
+            .position(|(s, _)| *s == surface)
+        {
+            let (_, allowed) = self.pending_activations.remove(i);
+            self.activate(window, allowed);
+        }
+    }
+
+    // Brings the window's workspace into view first if it is elsewhere
+    pub fn focus_window(&mut self, window: Window) {
+        let surface = window.toplevel().wl_surface().clone();
+        let Some((workspace, ..)) = self.workspaces.find_window(&surface) else {
+            return;
+        };
+
+        if workspace != self.workspaces.active() {
+            self.workspaces.set_active(workspace, &mut self.space);
+            ipc::tag_focused(self);
+        }
+
+        self.space.raise_element(&window, true);
+        self.workspaces.set_active_window(window);
+        let keyboard = self.seat.get_keyboard().unwrap();
+        keyboard.set_focus(self, Some(surface), SERIAL_COUNTER.next_serial());
+    }
+
+    // Passed on to spawned commands as XDG_ACTIVATION_TOKEN, so their first
+    // window can take focus from whatever had it when the key was pressed
+    pub fn activation_token(&mut self) -> String {
+        let (token, _) = self.xdg_activation_state.create_external_token(None);
+        let token = token.to_string();
+
+        self.expire_tokens();
+        self.spawn_tokens.push((token.clone(), Instant::now()));
+        token
+    }
+
+    // Smithay keeps every token until it is removed, used or not
+    fn expire_tokens(&mut self) {
+        self.spawn_tokens
+            .retain(|(_, created)| created.elapsed() < TOKEN_LIFETIME);
+        self.xdg_activation_state
+            .retain_tokens(|_, data| data.timestamp.elapsed() < TOKEN_LIFETIME);
+    }
+
+    // Requests that don't pass the checks never take focus, at most they
+    // mark the window urgent
+    fn activate(&mut self, window: Window, allowed: bool) {
+        match (self.activation_policy, allowed) {
+            (ActivationPolicy::Ignore, _) => {}
+            (ActivationPolicy::Focus, true) => self.focus_window(window),
+            _ => self.set_urgent(&window, true),
+        }
+    }
+
+    // A client's own token must be recent and made for input it got while
+    // it had the keyboard
+    fn activation_allowed(
+        &mut self,
+        token: &XdgActivationToken,
+        data: &XdgActivationTokenData,
+    ) -> bool {
+        let token = token.to_string();
+        if let Some(i) = self.spawn_tokens.iter().position(|(t, _)| *t == token) {
+            let (_, created) = self.spawn_tokens.remove(i);
+            return created.elapsed() < TOKEN_LIFETIME;
+        }
+
+        let Some((serial, _)) = &data.serial else {
+            return false;
+        };
+        let last_enter = self.seat.get_keyboard().and_then(|k| k.last_enter());
+
+        data.timestamp.elapsed() < TOKEN_LIFETIME
+            && last_enter.is_some_and(|last| serial.is_no_older_than(&last))
+    }
+
+    pub fn set_urgent(&mut self, window: &Window, urgent: bool) {
+        if self.workspaces.set_urgent(window, urgent) {
+            ipc::window_urgent(window, urgent);
+        }
+    }
 }
 
 impl BufferHandler for State {
@@ -58,6 +267,28 @@ impl CompositorHandler for State {
     fn commit(&mut self, surface: &WlSurface) {
         on_commit_buffer_handler::<Self>(surface);
 
+        if let Some(icon) = self.dnd_icon.as_mut().filter(|i| &i.surface == surface) {
+            let delta = with_states(surface, |states| {
+                states
+                    .cached_state
+                    .current::<SurfaceAttributes>()
+                    .buffer_delta
+                    .take()
+            });
+            if let Some(delta) = delta {
+                icon.offset += delta;
+            }
+        }
+
+        if let Some(idx) = self
+            .pending_windows
+            .iter()
+            .position(|w| w.toplevel().wl_surface() == surface)
+        {
+            let window = self.pending_windows.remove(idx);
+            self.place_window(window);
+        }
+
         if let Some(window) = self
             .space
             .elements()
@@ -80,22 +311,116 @@ impl CompositorHandler for State {
                 window.toplevel().send_pending_configure();
             }
         }
+
+        ipc::title_committed(self, surface);
     }
 }
 delegate_compositor!(State);
 
-impl ClientDndGrabHandler for State {}
-impl ServerDndGrabHandler for State {}
+impl ClientDndGrabHandler for State {
+    fn started(
+        &mut self,
+        _source: Option<WlDataSource>,
+        icon: Option<WlSurface>,
+        _seat: Seat<Self>,
+    ) {
+        self.dnd_icon = icon.map(|surface| DndIcon {
+            surface,
+            offset: (0, 0).into(),
+        });
+    }
+
+    // Called on release whether the target accepted the drop or not
+    fn dropped(&mut self, _seat: Seat<Self>) {
+        self.dnd_icon = None;
+    }
+}
+// sswm never starts a drag itself, so a target asking it for the data gets the
+// pipe closed on it, the same as an empty transfer
+impl ServerDndGrabHandler for State {
+    fn send(&mut self, mime_type: String, fd: OwnedFd, _seat: Seat<Self>) {
+        warn!("no data for a drag sswm didn't start ({mime_type})");
+        drop(fd);
+    }
+}
 
 impl DataDeviceHandler for State {
-    type SelectionUserData = ();
+    type SelectionUserData = SelectionData;
 
     fn data_device_state(&self) -> &DataDeviceState {
         &self.data_device_state
     }
+
+    fn new_selection(&mut self, source: Option<WlDataSource>, seat: Seat<Self>) {
+        // Bumped on clears too, so a read still going can't bring the old
+        // contents back
+        let generation = self.selection_generations.next(SelectionTarget::Clipboard);
+
+        if let (Some(sender), Some(source)) = (&self.clipboard_keeper, source) {
+            let mime_types =
+                data_device::with_source_metadata(&source, |metadata| metadata.mime_types.clone())
+                    .unwrap_or_default();
+
+            keep_selection(
+                SelectionTarget::Clipboard,
+                generation,
+                mime_types,
+                |mime_type, fd| request_data_device_client_selection(&seat, mime_type, fd).is_ok(),
+                sender.clone(),
+            );
+        }
+    }
+
+    fn send_selection(
+        &mut self,
+        mime_type: String,
+        fd: OwnedFd,
+        _seat: Seat<Self>,
+        user_data: &Self::SelectionUserData,
+    ) {
+        send_kept_selection(user_data, &mime_type, fd);
+    }
 }
 delegate_data_device!(State);
 
+impl PrimarySelectionHandler for State {
+    type SelectionUserData = SelectionData;
+
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+
+    fn new_selection(&mut self, source: Option<ZwpPrimarySelectionSourceV1>, seat: Seat<Self>) {
+        let generation = self.selection_generations.next(SelectionTarget::Primary);
+
+        if let (Some(sender), Some(source)) = (&self.clipboard_keeper, source) {
+            let mime_types = primary_selection::with_source_metadata(&source, |metadata| {
+                metadata.mime_types.clone()
+            })
+            .unwrap_or_default();
+
+            keep_selection(
+                SelectionTarget::Primary,
+                generation,
+                mime_types,
+                |mime_type, fd| request_primary_client_selection(&seat, mime_type, fd).is_ok(),
+                sender.clone(),
+            );
+        }
+    }
+
+    fn send_selection(
+        &mut self,
+        mime_type: String,
+        fd: OwnedFd,
+        _seat: Seat<Self>,
+        user_data: &Self::SelectionUserData,
+    ) {
+        send_kept_selection(user_data, &mime_type, fd);
+    }
+}
+delegate_primary_selection!(State);
+
 impl SeatHandler for State {
     type KeyboardFocus = WlSurface;
     type PointerFocus = WlSurface;
@@ -104,15 +429,26 @@ impl SeatHandler for State {
         &mut self.seat_state
     }
 
-    fn cursor_image(
-        &mut self,
-        _: &smithay::input::Seat<Self>,
-        image: smithay::input::pointer::CursorImageStatus,
-    ) {
+    fn cursor_image(&mut self, _: &Seat<Self>, image: CursorImageStatus) {
         self.cursor_status = image;
     }
 
-    fn focus_changed(&mut self, _: &smithay::input::Seat<Self>, _: Option<&WlSurface>) {}
+    fn focus_changed(&mut self, seat: &Seat<Self>, focused: Option<&WlSurface>) {
+        let dh = &self.display_handle;
+        let client = focused.and_then(|surface| dh.get_client(surface.id()).ok());
+        set_data_device_focus(dh, seat, client.clone());
+        set_primary_focus(dh, seat, client);
+
+        // Focusing a window answers its request for attention
+        let window = focused
+            .and_then(|surface| self.workspaces.find_window(surface))
+            .map(|(_, window, ..)| window.clone());
+        if let Some(window) = window {
+            self.set_urgent(&window, false);
+        }
+
+        ipc::window_focused(self, focused);
+    }
 }
 delegate_seat!(State);
 
@@ -129,17 +465,22 @@ impl XdgShellHandler for State {
     }
 
     fn new_toplevel(&mut self, surface: ToplevelSurface) {
-        let window = Window::new(surface);
-
-        self.workspaces
-            .insert_window(self.workspaces.active(), window.clone());
-        self.space.map_element(window, (0, 0), false);
-
-        self.workspaces.refresh_geometry(&mut self.space);
+        self.pending_windows.push(Window::new(surface));
     }
 
-    fn toplevel_destroyed(&mut self, _: ToplevelSurface) {
+    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
+        self.pending_windows.retain(|w| w.toplevel() != &surface);
+        self.pending_activations
+            .retain(|(s, _)| s != surface.wl_surface());
+        let closed = self
+            .workspaces
+            .find_window(surface.wl_surface())
+            .map(|(_, window, ..)| window.clone());
         self.workspaces.refresh_geometry(&mut self.space);
+
+        if let Some(window) = closed {
+            ipc::window_closed(&window);
+        }
     }
 
     fn new_popup(&mut self, _: PopupSurface, _: PositionerState) {}
@@ -159,4 +500,35 @@ impl XdgShellHandler for State {
 }
 delegate_xdg_shell!(State);
 
+impl XdgActivationHandler for State {
+    fn activation_state(&mut self) -> &mut XdgActivationState {
+        &mut self.xdg_activation_state
+    }
+
+    // Each token is good for a single request
+    fn request_activation(
+        &mut self,
+        token: XdgActivationToken,
+        token_data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        let allowed = self.activation_allowed(&token, &token_data);
+        self.xdg_activation_state.remove_token(&token);
+        self.expire_tokens();
+
+        if let Some((_, window, ..)) = self.workspaces.find_window(&surface) {
+            let window = window.clone();
+            self.activate(window, allowed);
+        } else if self
+            .pending_windows
+            .iter()
+            .any(|w| w.toplevel().wl_surface() == &surface)
+        {
+            // Handled once the window is placed on its first commit
+            self.pending_activations.push((surface, allowed));
+        }
+    }
+}
+delegate_xdg_activation!(State);
+
 delegate_output!(State);
@@ -1,49 +1,78 @@
-use crate::wayland::{
-    data,
-    element::{PointerElement, PointerRenderElement},
-    input::Action,
-    state,
-    workspace::Workspaces,
+use crate::{
+    autostart::run_xdg_autostart,
+    config::Config,
+    ipc::start_server,
+    spawn::spawn,
+    startup::run_start_up,
+    supervisor,
+    wayland::{
+        data,
+        element::{PointerElement, PointerRenderElement},
+        input::{key_bindings, mode_bindings, Action, Chord, ChordStep, Modifiers},
+        ipc::{handle_request, tag_focused},
+        rules::window_rules,
+        selection::SelectionTarget,
+        state,
+        workspace::Workspaces,
+    },
 };
 use smithay::{
     backend::{
         input::{
-            AbsolutePositionEvent, ButtonState, Event, InputEvent, KeyState, KeyboardKeyEvent,
-            PointerButtonEvent,
+            AbsolutePositionEvent, Axis, AxisSource, ButtonState, Event, InputEvent, KeyState,
+            KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent, PointerMotionEvent,
         },
         renderer::{
             damage::OutputDamageTracker,
-            element::AsRenderElements,
+            element::{surface::render_elements_from_surface_tree, AsRenderElements},
             gles::{GlesRenderer, GlesTexture},
         },
-        winit::{self, WinitEvent},
+        winit::{self, WinitEvent, WinitInput},
     },
-    desktop::{space::render_output, Space, Window, WindowSurfaceType},
+    desktop::{space::render_output, utils::send_frames_surface_tree, Space, Window},
     input::{
-        keyboard::{keysyms, FilterResult},
-        pointer::{ButtonEvent, CursorImageStatus, MotionEvent},
+        keyboard::{keysyms, FilterResult, XkbConfig},
+        pointer::{
+            AxisFrame, ButtonEvent, CursorImageStatus, MotionEvent, PointerHandle,
+            RelativeMotionEvent,
+        },
         Seat, SeatState,
     },
     output,
     reexports::{
         calloop::{
+            channel::{self, Event as ChannelEvent},
             generic::Generic,
+            signals::{Signal, Signals},
             timer::{TimeoutAction, Timer},
             EventLoop, Interest, Mode, PostAction,
         },
-        wayland_server::{protocol::wl_surface::WlSurface, Display},
+        wayland_server::{protocol::wl_surface::WlSurface, Display, Resource},
     },
-    utils::{Clock, Scale, Transform, SERIAL_COUNTER},
+    utils::{Clock, Logical, Point, Scale, Transform, SERIAL_COUNTER},
     wayland::{
-        compositor::CompositorState, data_device::DataDeviceState, output::OutputManagerState,
-        shell::xdg::XdgShellState, shm::ShmState, socket::ListeningSocketSource,
+        compositor::CompositorState,
+        data_device::{set_data_device_selection, DataDeviceState},
+        output::OutputManagerState,
+        primary_selection::{set_primary_selection, PrimarySelectionState},
+        shell::xdg::XdgShellState,
+        shm::ShmState,
+        socket::ListeningSocketSource,
+        xdg_activation::XdgActivationState,
     },
 };
 use std::{convert::TryInto, os::unix::prelude::AsRawFd, sync::Arc, time::Duration};
+use tracing::warn;
 
-pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
+pub fn wayrun(cfg: Config) -> anyhow::Result<(), anyhow::Error> {
     let mut event_loop: EventLoop<data::Data> = EventLoop::try_new()?;
 
+    // Blocks SIGCHLD for this thread, so it has to come before any thread is spawned
+    let sigchld = Signals::new(&[Signal::SIGCHLD])?;
+    event_loop
+        .handle()
+        .insert_source(sigchld, |_, _, _| supervisor::reap())?;
+
     let mut display: Display<state::State> = Display::new()?;
 
     let socket = ListeningSocketSource::new_auto()?;
@@ -79,18 +108,67 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
     let shm_state = ShmState::new::<state::State>(&dh, vec![]);
     let output_manager_state = OutputManagerState::new_with_xdg_output::<state::State>(&dh);
     let xdg_shell_state = XdgShellState::new::<state::State>(&dh);
+    let xdg_activation_state = XdgActivationState::new::<state::State>(&dh);
     let mut seat_state = SeatState::<state::State>::new();
     let space = Space::<Window>::default();
     let data_device_state = DataDeviceState::new::<state::State>(&dh);
+    let primary_selection_state = PrimarySelectionState::new::<state::State>(&dh);
+
+    let clipboard_keeper = if cfg.clipboard_persistence {
+        let (sender, receiver) = channel::channel();
+
+        event_loop
+            .handle()
+            .insert_source(receiver, |event, _, data| {
+                if let ChannelEvent::Msg(kept) = event {
+                    // A client set a newer selection while this one was read
+                    if !data.state.selection_generations.is_current(&kept) {
+                        return;
+                    }
+
+                    let dh = data.display.handle();
+                    let seat = &data.state.seat;
+                    let mime_types = kept.data.keys().cloned().collect();
+                    let user_data = Arc::new(kept.data);
+
+                    match kept.target {
+                        SelectionTarget::Clipboard => {
+                            set_data_device_selection(&dh, seat, mime_types, user_data)
+                        }
+                        SelectionTarget::Primary => {
+                            set_primary_selection(&dh, seat, mime_types, user_data)
+                        }
+                    }
+                }
+            })?;
+
+        Some(sender)
+    } else {
+        None
+    };
 
     let mut seat: Seat<state::State> = seat_state.new_wl_seat(&dh, "sswm_seat");
-    seat.add_keyboard(Default::default(), 500, 500)?;
+    let keyboard = &cfg.keyboard;
+    let xkb_config = XkbConfig {
+        rules: &keyboard.rules,
+        model: &keyboard.model,
+        layout: &keyboard.layout,
+        variant: &keyboard.variant,
+        options: (!keyboard.options.is_empty()).then(|| keyboard.options.clone()),
+    };
+    let (repeat_delay, repeat_rate) = keyboard.repeat();
+    seat.add_keyboard(xkb_config, repeat_delay as i32, repeat_rate as i32)?;
     seat.add_pointer();
 
     let state = state::State {
         clock,
+        display_handle: dh.clone(),
         compositor_state,
         data_device_state,
+        primary_selection_state,
+        clipboard_keeper,
+        selection_generations: Default::default(),
+        seat: seat.clone(),
         seat_state,
         cursor_status: CursorImageStatus::Default,
         pointer_location: (0.0, 0.0).into(),
@@ -98,7 +176,18 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
         space,
         output_manager_state,
         xdg_shell_state,
-        workspaces: Workspaces::new(),
+        xdg_activation_state,
+        activation_policy: cfg.wactivation,
+        spawn_tokens: vec![],
+        workspaces: Workspaces::new(&cfg),
+        key_bindings: key_bindings(&cfg),
+        chord: Chord::new(&cfg.key_chords),
+        mode: None,
+        mode_bindings: mode_bindings(&cfg),
+        dnd_icon: None,
+        window_rules: window_rules(&cfg.wrules, &cfg.workspace_tags),
+        pending_windows: vec![],
+        pending_activations: vec![],
     };
 
     let mut data = data::Data { state, display };
@@ -130,10 +219,33 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
     output.set_preferred(mode);
     data.state.space.map_output(&output, (0, 0));
 
+    // Requests are answered from the event loop, which owns the state
+    let (ipc_sender, ipc_receiver) = channel::channel();
+    event_loop
+        .handle()
+        .insert_source(ipc_receiver, |event, _, data| {
+            if let ChannelEvent::Msg(request) = event {
+                let response = handle_request(&mut data.state, &request.request);
+                request.reply(response);
+            }
+        })?;
+    // Dropped, and the sockets removed, once the event loop stops
+    let _sockets = start_server(
+        move |request| {
+            let _ = ipc_sender.send(request);
+        },
+        cfg.i3_ipc,
+    )?;
+
     std::env::set_var("WAYLAND_DISPLAY", socket_name);
+    run_start_up(cfg.start_up.iter().chain(&cfg.wstart_up));
+    if cfg.xdg_autostart {
+        run_xdg_autostart();
+    }
 
     let start_time = std::time::Instant::now();
     let timer = Timer::immediate();
+    let signal = event_loop.get_signal();
 
     let mut output_damage_tracker = OutputDamageTracker::from_output(&output);
 
@@ -145,155 +257,17 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
             let display = &mut data.display;
             let state = &mut data.state;
 
-            winit
+            let closed = winit
                 .dispatch_new_events(|event| {
                     if let WinitEvent::Input(event) = event {
-                        if let InputEvent::Keyboard { event } = event {
-                            let serial = SERIAL_COUNTER.next_serial();
-                            let time = Event::time_msec(&event);
-                            let press_state = event.state();
-                            let action = seat.get_keyboard().unwrap().input::<Action, _>(
-                                state,
-                                event.key_code(),
-                                press_state,
-                                serial,
-                                time,
-                                |_, modifiers, handle| {
-                                    let keysym = handle.modified_sym();
-
-                                    if press_state == KeyState::Pressed
-                                        && (modifiers.logo)
-                                        && keysym == keysyms::KEY_f
-                                    {
-                                        return FilterResult::Intercept(Action::Spawn(
-                                            "firefox-dev".to_string(),
-                                        ));
-                                    }
-
-                                    if press_state == KeyState::Pressed
-                                        && (modifiers.logo)
-                                        && (keysym == keysyms::KEY_KP_Enter
-                                            || keysym == keysyms::KEY_A)
-                                    {
-                                        return FilterResult::Intercept(Action::Spawn(
-                                            "alacritty".to_string(),
-                                        ));
-                                    }
-
-                                    if press_state == KeyState::Pressed
-                                        && (modifiers.alt || modifiers.logo)
-                                        && (keysyms::KEY_1..=keysyms::KEY_9).contains(&keysym)
-                                    {
-                                        return if modifiers.logo {
-                                            FilterResult::Intercept(Action::WindowSetWorkspace(
-                                                (keysym - keysyms::KEY_1).try_into().unwrap(),
-                                            ))
-                                        } else {
-                                            FilterResult::Intercept(Action::WorkspaceSetActive(
-                                                (keysym - keysyms::KEY_1).try_into().unwrap(),
-                                            ))
-                                        };
-                                    }
-
-                                    FilterResult::Forward
-                                },
-                            );
-
-                            if let Some(action) = action {
-                                match action {
-                                    Action::WorkspaceSetActive(workspace) => {
-                                        state.workspaces.set_active(workspace, &mut state.space);
-                                    }
-                                    Action::WindowSetWorkspace(workspace) => {
-                                        state.workspaces.move_window(workspace, &mut state.space);
-                                    }
-                                    Action::Spawn(program) => {
-                                        std::process::Command::new(program).spawn().unwrap();
-                                    }
-                                }
-                            }
-                        }
-
-                        if let InputEvent::PointerButton { event, .. } = event {
-                            let pointer = seat.get_pointer().unwrap();
-                            let keyboard = seat.get_keyboard().unwrap();
-
-                            let serial = SERIAL_COUNTER.next_serial();
-
-                            let button = event.button_code();
-
-                            let button_state = event.state();
-
-                            if ButtonState::Pressed == button_state {
-                                if let Some((window, _loc)) = state
-                                    .space
-                                    .element_under(pointer.current_location())
-                                    .map(|(w, l)| (w.clone(), l))
-                                {
-                                    state.space.raise_element(&window, true);
-                                    keyboard.set_focus(
-                                        state,
-                                        Some(window.toplevel().wl_surface().clone()),
-                                        serial,
-                                    );
-                                    state.space.elements().for_each(|window| {
-                                        window.toplevel().send_pending_configure();
-                                    });
-                                } else {
-                                    state.space.elements().for_each(|window| {
-                                        window.set_activated(false);
-                                        window.toplevel().send_pending_configure();
-                                    });
-                                    keyboard.set_focus(state, Option::<WlSurface>::None, serial);
-                                }
-                            };
-
-                            pointer.button(
-                                state,
-                                &ButtonEvent {
-                                    button,
-                                    state: button_state,
-                                    serial,
-                                    time: event.time_msec(),
-                                },
-                            );
-                        }
-
-                        if let InputEvent::PointerMotionAbsolute { event, .. } = event {
-                            let output = state.space.outputs().next().unwrap();
-                            let output_geo = state.space.output_geometry(output).unwrap();
-                            let pointer_location = event.position_transformed(output_geo.size);
-
-                            state.pointer_location = pointer_location;
-
-                            let pointer = seat.get_pointer().unwrap();
-
-                            let element_with_location = state.space.element_under(pointer_location);
-
-                            if let Some((window, location)) = element_with_location {
-                                state.workspaces.set_active_window(window.clone());
-
-                                let surface_under_pointer = window
-                                    .surface_under(
-                                        pointer_location - location.to_f64(),
-                                        WindowSurfaceType::ALL,
-                                    )
-                                    .map(|(s, p)| (s, p + location));
-                                let serial = SERIAL_COUNTER.next_serial();
-                                pointer.motion(
-                                    state,
-                                    surface_under_pointer,
-                                    &MotionEvent {
-                                        location: pointer_location,
-                                        serial,
-                                        time: event.time_msec(),
-                                    },
-                                );
-                            }
-                        }
+                        handle_input(state, &seat, event);
                     }
                 })
-                .unwrap();
+                .is_err();
+            if closed {
+                signal.stop();
+                return TimeoutAction::Drop;
+            }
 
             backend.bind().unwrap();
 
@@ -304,12 +278,34 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
             let cursor_pos = state.pointer_location;
             let cursor_pos_scaled = cursor_pos.to_physical(scale).to_i32_round();
 
-            let elements = pointer_element.render_elements::<PointerRenderElement<GlesRenderer>>(
-                backend.renderer(),
-                cursor_pos_scaled,
-                scale,
-                1.0,
-            );
+            let mut elements = pointer_element
+                .render_elements::<PointerRenderElement<GlesRenderer>>(
+                    backend.renderer(),
+                    cursor_pos_scaled,
+                    scale,
+                    1.0,
+                );
+
+            if state
+                .dnd_icon
+                .as_ref()
+                .is_some_and(|icon| !icon.surface.alive())
+            {
+                state.dnd_icon = None;
+            }
+
+            if let Some(icon) = &state.dnd_icon {
+                let icon_pos = (cursor_pos + icon.offset.to_f64())
+                    .to_physical(scale)
+                    .to_i32_round();
+                elements.extend(render_elements_from_surface_tree(
+                    backend.renderer(),
+                    &icon.surface,
+                    icon_pos,
+                    scale,
+                    1.0,
+                ));
+            }
 
             let age = backend.buffer_age().unwrap_or(0);
 
@@ -336,6 +332,16 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
                 )
             });
 
+            if let Some(icon) = &state.dnd_icon {
+                send_frames_surface_tree(
+                    &icon.surface,
+                    &output,
+                    start_time.elapsed(),
+                    Some(Duration::ZERO),
+                    |_, _| Some(output.clone()),
+                );
+            }
+
             state.space.refresh();
 
             display.flush_clients().unwrap();
@@ -348,3 +354,257 @@ pub fn wayrun() -> anyhow::Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+fn pointer_motion(
+    state: &mut state::State,
+    pointer: &PointerHandle<state::State>,
+    pointer_location: Point<f64, Logical>,
+    time: u32,
+) -> Option<(WlSurface, Point<i32, Logical>)> {
+    state.pointer_location = pointer_location;
+
+    if let Some((window, _)) = state.space.element_under(pointer_location) {
+        state.workspaces.set_active_window(window.clone());
+    }
+
+    // A `None` focus makes the pointer send `leave` to whatever it was over,
+    // so moving onto the background is reported like any other crossing.
+    let under = state.surface_under(pointer_location);
+    let serial = SERIAL_COUNTER.next_serial();
+    pointer.motion(
+        state,
+        under.clone(),
+        &MotionEvent {
+            location: pointer_location,
+            serial,
+            time,
+        },
+    );
+
+    under
+}
+
+pub fn run_action(state: &mut state::State, seat: &Seat<state::State>, action: Action) {
+    match action {
+        Action::WorkspaceSetActive(workspace) => {
+            state.workspaces.set_active(workspace, &mut state.space);
+            tag_focused(state);
+        }
+        Action::WindowSetWorkspace(workspace) => {
+            state.workspaces.move_window(workspace, &mut state.space);
+        }
+        Action::FocusTag(tag) => match state.workspaces.position(&tag) {
+            Some(workspace) => {
+                state.workspaces.set_active(workspace, &mut state.space);
+                tag_focused(state);
+            }
+            None => warn!("unknown tag: {tag}"),
+        },
+        Action::MoveToTag(tag) => match state.workspaces.position(&tag) {
+            Some(workspace) => state.workspaces.move_window(workspace, &mut state.space),
+            None => warn!("unknown tag: {tag}"),
+        },
+        Action::FocusUrgent => {
+            if let Some(window) = state.workspaces.last_urgent().cloned() {
+                state.focus_window(window);
+            }
+        }
+        Action::Spawn(mut command) => {
+            command
+                .env
+                .insert("XDG_ACTIVATION_TOKEN".into(), state.activation_token());
+            spawn(&command);
+        }
+        Action::SwitchKeyboardLayout => {
+            let keyboard = seat.get_keyboard().unwrap();
+            keyboard.with_xkb_state(state, |mut context| context.cycle_next_layout());
+        }
+        Action::EnterMode(mode) => {
+            if state.mode_bindings.contains_key(&mode) {
+                state.mode = Some(mode);
+            } else {
+                warn!("unknown mode: {mode}");
+            }
+        }
+        Action::ExitMode => state.mode = None,
+        Action::Ignore => {}
+    }
+}
+
+fn handle_input(
+    state: &mut state::State,
+    seat: &Seat<state::State>,
+    event: InputEvent<WinitInput>,
+) {
+    match event {
+        InputEvent::Keyboard { event } => {
+            let serial = SERIAL_COUNTER.next_serial();
+            let time = Event::time_msec(&event);
+            let press_state = event.state();
+            let action = seat.get_keyboard().unwrap().input::<Action, _>(
+                state,
+                event.key_code(),
+                press_state,
+                serial,
+                time,
+                |state, modifiers, handle| {
+                    if press_state != KeyState::Pressed {
+                        return FilterResult::Forward;
+                    }
+
+                    let modifiers = Modifiers::from(modifiers);
+
+                    let bindings = match &state.mode {
+                        Some(mode) => &state.mode_bindings[mode],
+                        None => &state.key_bindings,
+                    };
+
+                    match state.chord.press(bindings, modifiers, handle.raw_syms()) {
+                        ChordStep::Run(action) => return FilterResult::Intercept(action),
+                        ChordStep::Swallow => return FilterResult::Intercept(Action::Ignore),
+                        // Unbound keys don't leave a mode or reach clients
+                        ChordStep::Unbound if state.mode.is_some() => {
+                            return FilterResult::Intercept(Action::Ignore)
+                        }
+                        ChordStep::Unbound => {}
+                    }
+
+                    let keysym = handle.modified_sym();
+
+                    if (modifiers.alt || modifiers.logo)
+                        && (keysyms::KEY_1..=keysyms::KEY_9).contains(&keysym)
+                    {
+                        return if modifiers.logo {
+                            FilterResult::Intercept(Action::WindowSetWorkspace(
+                                (keysym - keysyms::KEY_1).try_into().unwrap(),
+                            ))
+                        } else {
+                            FilterResult::Intercept(Action::WorkspaceSetActive(
+                                (keysym - keysyms::KEY_1).try_into().unwrap(),
+                            ))
+                        };
+                    }
+
+                    FilterResult::Forward
+                },
+            );
+
+            if let Some(action) = action {
+                run_action(state, seat, action);
+            }
+        }
+
+        InputEvent::PointerButton { event, .. } => {
+            let pointer = seat.get_pointer().unwrap();
+            let keyboard = seat.get_keyboard().unwrap();
+
+            let serial = SERIAL_COUNTER.next_serial();
+
+            let button = event.button_code();
+
+            let button_state = event.state();
+
+            if ButtonState::Pressed == button_state {
+                if let Some((window, _loc)) = state
+                    .space
+                    .element_under(pointer.current_location())
+                    .map(|(w, l)| (w.clone(), l))
+                {
+                    state.space.raise_element(&window, true);
+                    keyboard.set_focus(state, Some(window.toplevel().wl_surface().clone()), serial);
+                    state.space.elements().for_each(|window| {
+                        window.toplevel().send_pending_configure();
+                    });
+                } else {
+                    state.space.elements().for_each(|window| {
+                        window.set_activated(false);
+                        window.toplevel().send_pending_configure();
+                    });
+                    keyboard.set_focus(state, Option::<WlSurface>::None, serial);
+                }
+            };
+
+            pointer.button(
+                state,
+                &ButtonEvent {
+                    button,
+                    state: button_state,
+                    serial,
+                    time: event.time_msec(),
+                },
+            );
+        }
+
+        InputEvent::PointerMotionAbsolute { event, .. } => {
+            let output = state.space.outputs().next().unwrap();
+            let output_geo = state.space.output_geometry(output).unwrap();
+            let pointer_location = event.position_transformed(output_geo.size);
+
+            let pointer = seat.get_pointer().unwrap();
+            pointer_motion(state, &pointer, pointer_location, event.time_msec());
+        }
+
+        InputEvent::PointerMotion { event, .. } => {
+            let output = state.space.outputs().next().unwrap();
+            let output_geo = state.space.output_geometry(output).unwrap();
+
+            let mut pointer_location = state.pointer_location + event.delta();
+            pointer_location.x = pointer_location
+                .x
+                .clamp(0.0, (output_geo.size.w - 1) as f64);
+            pointer_location.y = pointer_location
+                .y
+                .clamp(0.0, (output_geo.size.h - 1) as f64);
+
+            let pointer = seat.get_pointer().unwrap();
+            let under = pointer_motion(state, &pointer, pointer_location, event.time_msec());
+
+            pointer.relative_motion(
+                state,
+                under,
+                &RelativeMotionEvent {
+                    delta: event.delta(),
+                    delta_unaccel: event.delta_unaccel(),
+                    utime: event.time(),
+                },
+            );
+        }
+
+        InputEvent::PointerAxis { event, .. } => {
+            let source = event.source();
+
+            let horizontal_amount = event
+                .amount(Axis::Horizontal)
+                .unwrap_or_else(|| event.amount_discrete(Axis::Horizontal).unwrap_or(0.0) * 3.0);
+            let vertical_amount = event
+                .amount(Axis::Vertical)
+                .unwrap_or_else(|| event.amount_discrete(Axis::Vertical).unwrap_or(0.0) * 3.0);
+            let horizontal_amount_discrete = event.amount_discrete(Axis::Horizontal);
+            let vertical_amount_discrete = event.amount_discrete(Axis::Vertical);
+
+            let mut frame = AxisFrame::new(event.time_msec()).source(source);
+
+            if horizontal_amount != 0.0 {
+                frame = frame.value(Axis::Horizontal, horizontal_amount);
+                if let Some(discrete) = horizontal_amount_discrete {
+                    frame = frame.discrete(Axis::Horizontal, discrete as i32);
+                }
+            } else if source == AxisSource::Finger {
+                frame = frame.stop(Axis::Horizontal);
+            }
+
+            if vertical_amount != 0.0 {
+                frame = frame.value(Axis::Vertical, vertical_amount);
+                if let Some(discrete) = vertical_amount_discrete {
+                    frame = frame.discrete(Axis::Vertical, discrete as i32);
+                }
+            } else if source == AxisSource::Finger {
+                frame = frame.stop(Axis::Vertical);
+            }
+
+            seat.get_pointer().unwrap().axis(state, frame);
+        }
+
+        _ => {}
+    }
+}
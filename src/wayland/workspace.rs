@@ -1,38 +1,258 @@
-use smithay::desktop::{Space, Window};
+use crate::config::{Config, NewWindow};
+use smithay::{
+    desktop::{Space, Window},
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Rectangle},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct FloatGeometry {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl FloatGeometry {
+    fn rect_in(&self, output: Rectangle<i32, Logical>) -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size(
+            (
+                output.loc.x + (output.size.w as f64 * self.x) as i32,
+                output.loc.y + (output.size.h as f64 * self.y) as i32,
+            ),
+            (
+                (output.size.w as f64 * self.w) as i32,
+                (output.size.h as f64 * self.h) as i32,
+            ),
+        )
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct TileSettings {
+    pub monocle: bool,
+    pub max_main: usize,
+    pub ratio: f32,
+    pub outer_gap: i32,
+    pub inner_gap: i32,
+    pub new_window: NewWindow,
+}
+
+impl TileSettings {
+    // Workspaces are matched to `workspace_tags` by position, the same way
+    // the number keys pick them. Wayland only knows `monocle`, every other
+    // layout name tiles. Without tag settings it keeps tiling the way it
+    // always has, one main window at half the width with 6px gaps, whatever
+    // the X layout settings are.
+    fn for_workspace(cfg: &Config, workspace: usize) -> Self {
+        let mut settings = TileSettings {
+            monocle: cfg
+                .wlayout
+                .as_deref()
+                .is_some_and(|l| l.eq_ignore_ascii_case("monocle")),
+            max_main: 1,
+            ratio: 0.5,
+            outer_gap: 6,
+            inner_gap: 6,
+            new_window: NewWindow::Stack,
+        };
+
+        let tag = cfg
+            .workspace_tags
+            .get(workspace)
+            .and_then(|tag| cfg.tag_settings.iter().find(|t| &t.tag == tag));
+
+        if let Some(tag) = tag {
+            if let Some(layout) = &tag.layout {
+                settings.monocle = layout.eq_ignore_ascii_case("monocle");
+            }
+            settings.max_main = tag.max_main.map_or(settings.max_main, |m| m as usize);
+            settings.ratio = tag.ratio.unwrap_or(settings.ratio);
+            settings.outer_gap = tag.outer_gaps.map_or(settings.outer_gap, |g| g as i32);
+            settings.inner_gap = tag.inner_gaps.map_or(settings.inner_gap, |g| g as i32);
+            settings.new_window = tag.new_window.unwrap_or(settings.new_window);
+        }
+
+        settings
+    }
+
+    fn tile(&self, area: Rectangle<i32, Logical>, n: usize) -> Vec<Rectangle<i32, Logical>> {
+        let gap = self.outer_gap;
+        let area = Rectangle::from_loc_and_size(
+            (area.loc.x + gap, area.loc.y + gap),
+            (area.size.w - gap * 2, area.size.h - gap * 2),
+        );
+
+        if self.monocle {
+            return vec![area; n];
+        }
+
+        let n_main = self.max_main.min(n);
+        if n_main == 0 || n_main == n {
+            return rows(area, n, self.inner_gap);
+        }
+
+        let main_width = ((area.size.w - self.inner_gap) as f32 * self.ratio) as i32;
+        let main = Rectangle::from_loc_and_size(area.loc, (main_width, area.size.h));
+        let stack = Rectangle::from_loc_and_size(
+            (area.loc.x + main_width + self.inner_gap, area.loc.y),
+            (area.size.w - main_width - self.inner_gap, area.size.h),
+        );
+
+        let mut rects = rows(main, n_main, self.inner_gap);
+        rects.extend(rows(stack, n - n_main, self.inner_gap));
+        rects
+    }
+}
+
+fn rows(area: Rectangle<i32, Logical>, n: usize, gap: i32) -> Vec<Rectangle<i32, Logical>> {
+    let n = n as i32;
+    let height = (area.size.h - gap * (n - 1)) / n.max(1);
+
+    (0..n)
+        .map(|i| {
+            let y = area.loc.y + i * (height + gap);
+            let h = if i + 1 == n {
+                area.loc.y + area.size.h - y
+            } else {
+                height
+            };
+            Rectangle::from_loc_and_size((area.loc.x, y), (area.size.w, h))
+        })
+        .collect()
+}
 
 struct Workspace {
+    settings: TileSettings,
     windows: Vec<Window>,
-    active_window: Option<usize>,
+    floating: Vec<(Window, FloatGeometry)>,
+    fullscreen: Option<Window>,
+    // Held by value, indices go stale as windows are added and removed
+    active_window: Option<Window>,
 }
 
 impl Workspace {
-    pub fn new() -> Self {
+    pub fn new(settings: TileSettings) -> Self {
         Self {
+            settings,
             windows: Vec::new(),
+            floating: Vec::new(),
+            fullscreen: None,
             active_window: None,
         }
     }
+
+    fn all_windows(&self) -> impl Iterator<Item = &Window> {
+        self.windows
+            .iter()
+            .chain(self.floating.iter().map(|(w, _)| w))
+            .chain(self.fullscreen.iter())
+    }
 }
 
 pub struct Workspaces {
     workspaces: Vec<Workspace>,
+    names: Vec<String>,
+    // Windows asking for attention, the most recent one last
+    urgent: Vec<Window>,
     active_workspace: usize,
     previous_workspace: usize,
 }
 
 impl Workspaces {
-    pub fn new() -> Self {
+    pub fn new(cfg: &Config) -> Self {
         Self {
-            workspaces: (0..=8).map(|_| Workspace::new()).collect(),
+            workspaces: (0..=8)
+                .map(|i| Workspace::new(TileSettings::for_workspace(cfg, i)))
+                .collect(),
+            names: (0..=8)
+                .map(|i| {
+                    cfg.workspace_tags
+                        .get(i)
+                        .cloned()
+                        .unwrap_or_else(|| (i + 1).to_string())
+                })
+                .collect(),
+            urgent: Vec::new(),
             active_workspace: 0,
             previous_workspace: 0,
         }
     }
 
+    pub fn count(&self) -> usize {
+        self.workspaces.len()
+    }
+
     pub fn active(&self) -> usize {
         self.active_workspace
     }
 
+    pub fn name(&self, workspace: usize) -> &str {
+        &self.names[workspace]
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    pub fn layout_name(&self, workspace: usize) -> &'static str {
+        if self.workspaces[workspace].settings.monocle {
+            "monocle"
+        } else {
+            "tile"
+        }
+    }
+
+    // Each window along with whether it is floating and whether it is fullscreen
+    pub fn windows(&self, workspace: usize) -> impl Iterator<Item = (&Window, bool, bool)> {
+        let workspace = &self.workspaces[workspace];
+
+        workspace
+            .windows
+            .iter()
+            .map(|w| (w, false, false))
+            .chain(workspace.floating.iter().map(|(w, _)| (w, true, false)))
+            .chain(workspace.fullscreen.iter().map(|w| (w, false, true)))
+    }
+
+    pub fn find_window(&self, surface: &WlSurface) -> Option<(usize, &Window, bool, bool)> {
+        (0..self.count()).find_map(|i| {
+            self.windows(i)
+                .find(|(w, ..)| w.toplevel().wl_surface() == surface)
+                .map(|(w, floating, fullscreen)| (i, w, floating, fullscreen))
+        })
+    }
+
+    pub fn is_urgent(&self, window: &Window) -> bool {
+        self.urgent.contains(window)
+    }
+
+    pub fn has_urgent(&self, workspace: usize) -> bool {
+        self.windows(workspace).any(|(w, ..)| self.is_urgent(w))
+    }
+
+    pub fn last_urgent(&self) -> Option<&Window> {
+        self.urgent.last()
+    }
+
+    // Returns whether the window's urgency changed
+    pub fn set_urgent(&mut self, window: &Window, urgent: bool) -> bool {
+        let was = self.is_urgent(window);
+        self.urgent.retain(|w| w != window);
+        if urgent {
+            self.urgent.push(window.clone());
+        }
+        was != urgent
+    }
+
+    pub fn active_window(&self) -> Option<&Window> {
+        let workspace = &self.workspaces[self.active_workspace];
+        workspace.active_window.as_ref()
+    }
+
     pub fn set_active(&mut self, workspace: usize, space: &mut Space<Window>) {
         self.previous_workspace = self.active_workspace;
         self.active_workspace = workspace;
@@ -41,20 +261,45 @@ impl Workspaces {
 
     pub fn set_active_window(&mut self, window: Window) {
         let workspace = &mut self.workspaces[self.active_workspace];
-        workspace.active_window = workspace.windows.iter().position(|w| w == &window);
+        workspace.active_window = workspace.windows.contains(&window).then_some(window);
     }
 
     pub fn insert_window(&mut self, workspace: usize, window: Window) {
-        self.workspaces[workspace].windows.push(window.clone());
+        let workspace = &mut self.workspaces[workspace];
+
+        match workspace.settings.new_window {
+            NewWindow::Main => workspace.windows.insert(0, window),
+            NewWindow::Stack => workspace.windows.push(window),
+        }
+    }
+
+    pub fn insert_floating(&mut self, workspace: usize, window: Window, geometry: FloatGeometry) {
+        self.workspaces[workspace].floating.push((window, geometry));
+    }
+
+    pub fn insert_fullscreen(&mut self, workspace: usize, window: Window) {
+        window.toplevel().with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+        });
+
+        // Only one window can cover the output, the previous one goes back to tiling
+        if let Some(previous) = self.workspaces[workspace].fullscreen.replace(window) {
+            previous.toplevel().with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Fullscreen);
+            });
+            self.insert_window(workspace, previous);
+        }
     }
 
     pub fn move_window(&mut self, workspace: usize, space: &mut Space<Window>) {
-        if let Some(active_window) = self.workspaces[self.active_workspace].active_window {
-            let window = self.workspaces[self.active_workspace].windows[active_window].clone();
+        let active = &mut self.workspaces[self.active_workspace];
+        let window = active
+            .active_window
+            .take()
+            .filter(|w| active.windows.contains(w));
 
-            self.workspaces[self.active_workspace]
-                .windows
-                .retain(|w| w != &window);
+        if let Some(window) = window {
+            active.windows.retain(|w| w != &window);
             self.insert_window(workspace, window);
             self.refresh_geometry(space);
         }
@@ -63,50 +308,65 @@ impl Workspaces {
     pub fn refresh_geometry(&mut self, space: &mut Space<Window>) {
         space.refresh();
 
+        self.urgent.retain(|w| w.toplevel().alive());
+        for workspace in &mut self.workspaces {
+            workspace.windows.retain(|w| w.toplevel().alive());
+            workspace.floating.retain(|(w, _)| w.toplevel().alive());
+            if workspace
+                .active_window
+                .as_ref()
+                .is_some_and(|w| !w.toplevel().alive())
+            {
+                workspace.active_window = None;
+            }
+            if workspace
+                .fullscreen
+                .as_ref()
+                .is_some_and(|w| !w.toplevel().alive())
+            {
+                workspace.fullscreen = None;
+            }
+        }
+
         self.workspaces[self.previous_workspace]
-            .windows
-            .iter()
+            .all_windows()
             .for_each(|window| space.unmap_elem(window));
 
         let output = space.outputs().next().cloned().unwrap();
 
         let output_geometry = space.output_geometry(&output).unwrap();
-        let output_width = output_geometry.size.w;
-        let output_height = output_geometry.size.h;
-
-        let gap = 6;
-
-        let windows = &mut self.workspaces[self.active_workspace].windows;
-
-        let elements_count = windows.len() as i32;
+        let workspace = &mut self.workspaces[self.active_workspace];
+        let rects = workspace
+            .settings
+            .tile(output_geometry, workspace.windows.len());
 
-        for (i, window) in windows.iter().enumerate() {
-            let (mut x, mut y) = (gap, gap);
-            let (mut width, mut height) = (output_width - gap * 2, output_height - gap * 2);
+        for (window, rect) in workspace.windows.iter().zip(rects) {
+            window.toplevel().with_pending_state(|state| {
+                state.size = Some(rect.size);
+            });
+            window.toplevel().send_pending_configure();
 
-            if elements_count > 1 {
-                width -= gap;
-                width /= 2;
-            }
+            space.map_element(window.clone(), rect.loc, false);
+        }
 
-            if i > 0 {
-                height /= elements_count - 1;
+        for (window, geometry) in &workspace.floating {
+            let rect = geometry.rect_in(output_geometry);
 
-                x += width + gap;
-                y += height * (i as i32 - 1);
-            }
+            window.toplevel().with_pending_state(|state| {
+                state.size = Some(rect.size);
+            });
+            window.toplevel().send_pending_configure();
 
-            if i > 1 {
-                height -= gap;
-                y += gap;
-            }
+            space.map_element(window.clone(), rect.loc, true);
+        }
 
+        if let Some(window) = &workspace.fullscreen {
             window.toplevel().with_pending_state(|state| {
-                state.size = Some((width, height).into());
+                state.size = Some(output_geometry.size);
             });
             window.toplevel().send_pending_configure();
 
-            space.map_element(window.clone(), (x, y), false);
+            space.map_element(window.clone(), output_geometry.loc, true);
         }
     }
 }
@@ -0,0 +1,96 @@
+use penrose::{x11rb::RustConn, Color, Error, Result};
+use penrose_ui::{
+    bar::{
+        widgets::{
+            ActiveWindowName, CurrentLayout, RefreshText, RootWindowName, Widget, Workspaces,
+        },
+        Position,
+    },
+    core::TextStyle,
+    StatusBar,
+};
+use std::sync::{atomic::AtomicU32, Arc};
+
+use crate::{
+    config::Bar,
+    x::{tray::TraySpace, urgency::UrgentWindows},
+};
+
+const MAX_ACTIVE_WINDOW_CHARS: usize = 80;
+
+// Tags holding urgent windows, nothing when there are none
+fn urgent_text(urgent: &UrgentWindows) -> String {
+    let tags = urgent.tags();
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!("! {}", tags.join(" "))
+    }
+}
+
+// Tags, urgent tags, current layout, focused window title and the root window
+// name, which scripts set as status text with `xsetroot -name`.
+pub fn sswm_bar(
+    bar: &Bar,
+    urgent: u32,
+    urgent_windows: UrgentWindows,
+    tray: Option<(Arc<AtomicU32>, usize)>,
+) -> Result<StatusBar<RustConn>> {
+    let style = TextStyle {
+        fg: Color::new_from_hex(bar.foreground),
+        bg: Some(Color::new_from_hex(bar.background)),
+        padding: (2, 2),
+    };
+    let highlight = Color::new_from_hex(bar.highlight);
+
+    let mut widgets: Vec<Box<dyn Widget<RustConn>>> = vec![
+        Box::new(Workspaces::new(
+            style,
+            highlight,
+            Color::new_from_hex(bar.empty),
+        )),
+        Box::new(RefreshText::new(
+            TextStyle {
+                fg: Color::new_from_hex(urgent),
+                ..style
+            },
+            move || urgent_text(&urgent_windows),
+        )),
+        Box::new(CurrentLayout::new(style)),
+        Box::new(ActiveWindowName::new(
+            MAX_ACTIVE_WINDOW_CHARS,
+            TextStyle {
+                bg: Some(highlight),
+                padding: (6, 4),
+                ..style
+            },
+            true,
+            false,
+        )),
+        Box::new(RootWindowName::new(
+            TextStyle {
+                padding: (4, 2),
+                ..style
+            },
+            false,
+            true,
+        )),
+    ];
+    if let Some((width, screen)) = tray {
+        widgets.push(Box::new(TraySpace::new(
+            Color::new_from_hex(bar.background),
+            width,
+            screen,
+        )));
+    }
+
+    StatusBar::try_new(
+        Position::Top,
+        bar.height,
+        Color::new_from_hex(bar.background),
+        &bar.font,
+        bar.point_size,
+        widgets,
+    )
+    .map_err(|e| Error::Custom(e.to_string()))
+}
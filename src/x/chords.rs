@@ -0,0 +1,362 @@
+use penrose::{
+    core::{
+        bindings::{keycodes_from_xmodmap, KeyEventHandler},
+        hooks::EventHook,
+        State,
+    },
+    x::{XConn, XEvent},
+    x11rb::RustConn,
+    Error, Result,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    process::Command,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{
+        ClientMessageEvent, ConnectionExt as _, EventMask, GrabMode, GrabStatus, ModMask,
+    },
+    CURRENT_TIME,
+};
+
+use crate::{config::KeyChords, supervisor};
+
+// A modifier mask and keycode, the same pair penrose grabs for single keys
+pub type Key = (u16, u8);
+
+type Branch = HashMap<Key, (String, Node)>;
+
+const CHORD_TIMEOUT: &str = "_SSWM_CHORD_TIMEOUT";
+
+enum Node {
+    Leaf(Box<dyn KeyEventHandler<RustConn>>),
+    Branch(Branch),
+}
+
+fn x_err(e: impl std::fmt::Display) -> Error {
+    Error::Custom(e.to_string())
+}
+
+pub fn parse_key(codes: &HashMap<String, u8>, combo: &str) -> Option<Key> {
+    let mut parts: Vec<&str> = combo.split('-').collect();
+    let code = *codes.get(parts.pop()?)?;
+
+    let mut mask = 0;
+    for m in parts {
+        mask |= u16::from(match m {
+            "M" => ModMask::M4,
+            "A" => ModMask::M1,
+            "C" => ModMask::CONTROL,
+            "S" => ModMask::SHIFT,
+            _ => return None,
+        });
+    }
+
+    Some((mask, code))
+}
+
+// A sequence that has been started and is waiting for its next key
+struct Pending {
+    root: String,
+    path: Vec<Key>,
+    prefix: String,
+    deadline: Instant,
+    generation: u32,
+    modifier_codes: Vec<u8>,
+}
+
+#[derive(Default)]
+struct ChordState {
+    cfg: KeyChords,
+    sequences: Vec<(Vec<String>, Box<dyn KeyEventHandler<RustConn>>)>,
+    roots: HashMap<String, Branch>,
+    pending: Option<Pending>,
+    generation: u32,
+}
+
+impl ChordState {
+    fn branch(&mut self, root: &str, path: &[Key]) -> Option<&mut Branch> {
+        let mut branch = self.roots.get_mut(root)?;
+        for key in path {
+            branch = match branch.get_mut(key) {
+                Some((_, Node::Branch(next))) => next,
+                _ => return None,
+            };
+        }
+
+        Some(branch)
+    }
+}
+
+// Sequences like `M-a t` are collected here while the config is read. The
+// first combo is bound in penrose as usual and grabs the keyboard, the rest of
+// the sequence then arrives as ordinary key presses for the event hook below,
+// so penrose keeps handling everything else in the meantime.
+#[derive(Clone, Default)]
+pub struct Chords {
+    state: Rc<RefCell<ChordState>>,
+}
+
+fn grab_keyboard(x: &RustConn, grab: bool) -> Result<bool> {
+    let conn = x.connection();
+
+    if !grab {
+        conn.ungrab_keyboard(CURRENT_TIME)?;
+        conn.flush()?;
+        return Ok(true);
+    }
+
+    // Penrose already holds the keyboard until the prefix key is released,
+    // and a client can always take over its own grab
+    let status = conn
+        .grab_keyboard(
+            false,
+            *x.root(),
+            CURRENT_TIME,
+            GrabMode::ASYNC,
+            GrabMode::ASYNC,
+        )?
+        .reply()?
+        .status;
+
+    Ok(status == GrabStatus::SUCCESS)
+}
+
+fn send_timeout(generation: u32) -> Result<()> {
+    let (conn, screen_num) = x11rb::connect(None).map_err(x_err)?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom = conn
+        .intern_atom(false, CHORD_TIMEOUT.as_bytes())?
+        .reply()?
+        .atom;
+
+    let message = ClientMessageEvent::new(32, root, atom, [generation, 0, 0, 0, 0]);
+    conn.send_event(
+        false,
+        root,
+        EventMask::SUBSTRUCTURE_NOTIFY | EventMask::SUBSTRUCTURE_REDIRECT,
+        message,
+    )?;
+    conn.flush()?;
+    Ok(())
+}
+
+// Penrose has no timers, so a thread wakes the event loop with a client
+// message once the sequence has had its time
+fn start_timer(timeout: Duration, generation: u32) {
+    thread::spawn(move || {
+        thread::sleep(timeout);
+        if let Err(e) = send_timeout(generation) {
+            warn!("unable to time out the key sequence: {e}");
+        }
+    });
+}
+
+impl Chords {
+    pub fn new(cfg: &KeyChords) -> Self {
+        let chords = Self::default();
+        chords.state.borrow_mut().cfg = cfg.clone();
+        chords
+    }
+
+    pub fn insert(&mut self, keybind: &str, handler: Box<dyn KeyEventHandler<RustConn>>) {
+        let combos = keybind.split_whitespace().map(String::from).collect();
+        self.state.borrow_mut().sequences.push((combos, handler));
+    }
+
+    pub fn into_handlers(&self) -> Result<Vec<(String, Box<dyn KeyEventHandler<RustConn>>)>> {
+        let codes = keycodes_from_xmodmap()?;
+        let mut state = self.state.borrow_mut();
+        let sequences = std::mem::take(&mut state.sequences);
+
+        'sequences: for (combos, handler) in sequences {
+            let Some(keys) = combos[1..]
+                .iter()
+                .map(|c| parse_key(&codes, c).map(|k| (k, c.clone())))
+                .collect::<Option<Vec<_>>>()
+            else {
+                warn!("invalid key sequence: {}", combos.join(" "));
+                continue;
+            };
+
+            let mut branch = state.roots.entry(combos[0].clone()).or_default();
+            let (last, prefix) = keys.split_last().unwrap();
+
+            for (key, name) in prefix {
+                let node = branch
+                    .entry(*key)
+                    .or_insert_with(|| (name.clone(), Node::Branch(HashMap::new())));
+
+                branch = match &mut node.1 {
+                    Node::Branch(next) => next,
+                    Node::Leaf(_) => {
+                        warn!(
+                            "key sequence shadowed by a shorter one: {}",
+                            combos.join(" ")
+                        );
+                        continue 'sequences;
+                    }
+                };
+            }
+
+            // Later bindings override earlier ones, like single keys do
+            branch.insert(last.0, (last.1.clone(), Node::Leaf(handler)));
+        }
+
+        Ok(state
+            .roots
+            .keys()
+            .map(|root| {
+                let handler: Box<dyn KeyEventHandler<RustConn>> = Box::new(ChordRoot {
+                    chords: self.clone(),
+                    root: root.clone(),
+                });
+                (root.clone(), handler)
+            })
+            .collect())
+    }
+
+    fn start(&self, root: &str, x: &RustConn) -> Result<()> {
+        let modifier_codes = x.connection().get_modifier_mapping()?.reply()?.keycodes;
+        if !grab_keyboard(x, true)? {
+            warn!("unable to grab the keyboard for {root}");
+            return Ok(());
+        }
+
+        let mut state = self.state.borrow_mut();
+        let timeout = Duration::from_millis(state.cfg.timeout);
+        state.generation = state.generation.wrapping_add(1);
+        state.pending = Some(Pending {
+            root: root.to_string(),
+            path: vec![],
+            prefix: root.to_string(),
+            deadline: Instant::now() + timeout,
+            generation: state.generation,
+            modifier_codes,
+        });
+
+        show_hint(state.cfg.hint_command.as_deref(), root);
+        start_timer(timeout, state.generation);
+        Ok(())
+    }
+
+    fn abort(&self, x: &RustConn) -> Result<()> {
+        self.state.borrow_mut().pending = None;
+        grab_keyboard(x, false)?;
+        Ok(())
+    }
+
+    // Timing out or pressing a key that isn't bound aborts the sequence
+    fn key_press(
+        &self,
+        mask: u16,
+        code: u8,
+        state: &mut State<RustConn>,
+        x: &RustConn,
+    ) -> Result<()> {
+        let mods = u16::from(ModMask::SHIFT | ModMask::CONTROL | ModMask::M1 | ModMask::M4);
+        let key = (mask & mods, code);
+
+        let mut chords = self.state.borrow_mut();
+        let Some(pending) = chords.pending.as_ref() else {
+            return Ok(());
+        };
+        if pending.modifier_codes.contains(&code) {
+            return Ok(());
+        }
+
+        let (root, path) = (pending.root.clone(), pending.path.clone());
+        let node = if Instant::now() > pending.deadline {
+            None
+        } else {
+            chords
+                .branch(&root, &path)
+                .and_then(|b| b.get(&key))
+                .map(|(name, node)| (name.clone(), matches!(node, Node::Leaf(_))))
+        };
+
+        match node {
+            Some((_, true)) => {
+                chords.pending = None;
+                grab_keyboard(x, false)?;
+
+                match chords.branch(&root, &path).and_then(|b| b.get_mut(&key)) {
+                    Some((_, Node::Leaf(handler))) => handler.call(state, x),
+                    _ => Ok(()),
+                }
+            }
+            Some((name, false)) => {
+                let timeout = Duration::from_millis(chords.cfg.timeout);
+                chords.generation = chords.generation.wrapping_add(1);
+                let generation = chords.generation;
+                let hint_command = chords.cfg.hint_command.clone();
+
+                let pending = chords.pending.as_mut().unwrap();
+                pending.path.push(key);
+                pending.prefix = format!("{} {name}", pending.prefix);
+                pending.deadline = Instant::now() + timeout;
+                pending.generation = generation;
+
+                show_hint(hint_command.as_deref(), &pending.prefix);
+                start_timer(timeout, generation);
+                Ok(())
+            }
+            None => {
+                drop(chords);
+                self.abort(x)
+            }
+        }
+    }
+}
+
+impl EventHook<RustConn> for Chords {
+    fn call(&mut self, event: &XEvent, state: &mut State<RustConn>, x: &RustConn) -> Result<bool> {
+        let Some(generation) = self.state.borrow().pending.as_ref().map(|p| p.generation) else {
+            return Ok(true);
+        };
+
+        match event {
+            XEvent::KeyPress(k) => {
+                self.key_press(k.mask, k.code, state, x)?;
+                Ok(false)
+            }
+            XEvent::ClientMessage(m) if m.dtype == CHORD_TIMEOUT => {
+                // Timers for keys that have since been pressed are left to expire
+                if m.data().as_usize()[0] == generation as usize {
+                    self.abort(x)?;
+                }
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+// Bound to the first combo of a sequence in penrose
+struct ChordRoot {
+    chords: Chords,
+    root: String,
+}
+
+fn show_hint(hint_command: Option<&str>, prefix: &str) {
+    if let Some(hint) = hint_command {
+        // The pending keys are passed as an argument rather than spliced in
+        let mut command = Command::new("sh");
+        command.args(["-c", &format!("{hint} \"$1\""), "sh", prefix]);
+        if let Err(e) = supervisor::spawn("hint", command) {
+            warn!("unable to run the key chord hint: {e}");
+        }
+    }
+}
+
+impl KeyEventHandler<RustConn> for ChordRoot {
+    fn call(&mut self, _state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        self.chords.start(&self.root, x)
+    }
+}
@@ -0,0 +1,116 @@
+use penrose::{
+    core::{hooks::ManageHook, State},
+    pure::geometry::Rect,
+    x::{property::Prop, Query, XConn, XConnExt},
+    Result, Xid,
+};
+use regex::Regex;
+
+#[derive(Debug, Default, Clone)]
+pub struct WindowQuery {
+    pub class: Option<String>,
+    pub instance: Option<String>,
+    pub title: Option<Regex>,
+    pub window_type: Option<String>,
+    pub role: Option<String>,
+}
+
+impl WindowQuery {
+    fn is_empty(&self) -> bool {
+        self.class.is_none()
+            && self.instance.is_none()
+            && self.title.is_none()
+            && self.window_type.is_none()
+            && self.role.is_none()
+    }
+}
+
+impl<X> Query<X> for WindowQuery
+where
+    X: XConn,
+{
+    fn run(&self, id: Xid, x: &X) -> Result<bool> {
+        if self.is_empty() {
+            return Ok(false);
+        }
+
+        if let Some(title) = &self.title {
+            if !title.is_match(&x.window_title(id)?) {
+                return Ok(false);
+            }
+        }
+
+        if self.class.is_some() || self.instance.is_some() {
+            // WM_CLASS holds the instance name followed by the class name
+            let wm_class = match x.get_prop(id, "WM_CLASS")? {
+                Some(Prop::UTF8String(strs)) => strs,
+                _ => vec![],
+            };
+
+            if self.instance.is_some() && self.instance.as_ref() != wm_class.first() {
+                return Ok(false);
+            }
+
+            if self.class.is_some() && self.class.as_ref() != wm_class.get(1) {
+                return Ok(false);
+            }
+        }
+
+        if let Some(window_type) = &self.window_type {
+            // Both `dialog` and `_NET_WM_WINDOW_TYPE_DIALOG` are accepted
+            let wanted = window_type
+                .to_uppercase()
+                .trim_start_matches("_NET_WM_WINDOW_TYPE_")
+                .to_string();
+            let types = match x.get_prop(id, "_NET_WM_WINDOW_TYPE")? {
+                Some(Prop::Atom(atoms)) => atoms,
+                _ => vec![],
+            };
+
+            if !types
+                .iter()
+                .any(|t| t.trim_start_matches("_NET_WM_WINDOW_TYPE_") == wanted)
+            {
+                return Ok(false);
+            }
+        }
+
+        if let Some(role) = &self.role {
+            match x.get_prop(id, "WM_WINDOW_ROLE")? {
+                Some(Prop::UTF8String(strs)) if strs.first() == Some(role) => (),
+                _ => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatRelative {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl FloatRelative {
+    pub fn rect_in(&self, screen: Rect) -> Rect {
+        Rect::new(
+            screen.x + (screen.w as f64 * self.x) as u32,
+            screen.y + (screen.h as f64 * self.y) as u32,
+            (screen.w as f64 * self.w) as u32,
+            (screen.h as f64 * self.h) as u32,
+        )
+    }
+}
+
+impl<X> ManageHook<X> for FloatRelative
+where
+    X: XConn,
+{
+    fn call(&mut self, client: Xid, state: &mut State<X>, _x: &X) -> Result<()> {
+        let r = self.rect_in(state.client_set.current_screen().geometry());
+        state.client_set.float(client, r)
+    }
+}
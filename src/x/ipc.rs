@@ -0,0 +1,399 @@
+use penrose::{
+    core::{
+        bindings::KeyEventHandler,
+        hooks::{EventHook, StateHook},
+        layout::LayoutStack,
+        State,
+    },
+    pure::geometry::Rect,
+    x::{property::Prop, XConn, XEvent},
+    x11rb::RustConn,
+    Error, Result, Xid,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+};
+use tracing::warn;
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        randr::ConnectionExt as _,
+        xproto::{Atom, ClientMessageEvent, ConnectionExt as _, EventMask, Window as XWindow},
+    },
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    ipc::{
+        self,
+        protocol::{Event, Focus, Layouts, Output, Request, Response, Tag, Window},
+        IpcRequest, SocketGuard,
+    },
+    spawn::spawn,
+    x::urgency::urgent_windows,
+};
+
+// Sent to the root window to get penrose to look at the queued requests
+const WAKE_ATOM: &str = "_SSWM_IPC";
+
+pub type Actions = Box<dyn FnMut(&str) -> Option<Box<dyn KeyEventHandler<RustConn>>>>;
+
+fn x_err(e: impl std::fmt::Display) -> Error {
+    Error::Custom(e.to_string())
+}
+
+pub fn layout_names(stack: &LayoutStack) -> Vec<String> {
+    stack.iter().map(|l| l.name()).collect()
+}
+
+fn wake(conn: &RustConnection, root: XWindow, atom: Atom) -> std::result::Result<(), String> {
+    let event = ClientMessageEvent::new(32, root, atom, [0u32; 5]);
+    conn.send_event(
+        false,
+        root,
+        EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+        event,
+    )
+    .map_err(|e| e.to_string())?;
+    conn.flush().map_err(|e| e.to_string())
+}
+
+// The socket is served from other threads while penrose owns the state, so
+// requests are queued up and answered from an event hook.
+pub struct IpcHook {
+    requests: Receiver<IpcRequest>,
+    actions: Actions,
+    default_layouts: Vec<String>,
+    tag_layouts: HashMap<String, Vec<String>>,
+}
+
+impl IpcHook {
+    pub fn new(
+        actions: Actions,
+        default_layouts: Vec<String>,
+        tag_layouts: HashMap<String, Vec<String>>,
+        i3_compat: bool,
+    ) -> Result<(Self, SocketGuard)> {
+        let (conn, screen_num) = x11rb::connect(None).map_err(x_err)?;
+        let root = conn.setup().roots[screen_num].root;
+        let atom = conn
+            .intern_atom(false, WAKE_ATOM.as_bytes())
+            .map_err(x_err)?
+            .reply()
+            .map_err(x_err)?
+            .atom;
+        let conn = Arc::new(conn);
+
+        let (sender, requests) = mpsc::channel();
+        let sockets = ipc::start_server(
+            move |request| {
+                if sender.send(request).is_ok() {
+                    if let Err(e) = wake(&conn, root, atom) {
+                        warn!("unable to pass an ipc request on: {e}");
+                    }
+                }
+            },
+            i3_compat,
+        )
+        .map_err(x_err)?;
+
+        let hook = Self {
+            requests,
+            actions,
+            default_layouts,
+            tag_layouts,
+        };
+        Ok((hook, sockets))
+    }
+
+    fn handle(
+        &mut self,
+        request: &Request,
+        state: &mut State<RustConn>,
+        x: &RustConn,
+    ) -> Result<Response> {
+        Ok(match request {
+            Request::GetTags => Response::ok(tags(state)),
+            Request::GetWindows => Response::ok(windows(state, x)),
+            Request::GetOutputs => Response::ok(outputs(state, x)),
+            Request::GetLayouts => Response::ok(self.layouts(state)),
+            Request::GetFocus => Response::ok(focus(state, x)),
+            Request::RunAction { action } => match (self.actions)(action) {
+                Some(mut handler) => {
+                    handler.call(state, x)?;
+                    Response::done()
+                }
+                None => Response::error(format!("unknown action: {action}")),
+            },
+            Request::RunCommand { command, shell } => {
+                spawn(&ipc::command(command, *shell));
+                Response::done()
+            }
+            // Handled by the server itself
+            Request::Subscribe { .. } => Response::error("unexpected subscribe"),
+        })
+    }
+
+    fn layouts(&self, state: &State<RustConn>) -> Layouts {
+        let cs = &state.client_set;
+        let tag = cs.current_tag().to_string();
+
+        Layouts {
+            current: cs.current_workspace().layout_name(),
+            available: self
+                .tag_layouts
+                .get(&tag)
+                .unwrap_or(&self.default_layouts)
+                .clone(),
+            tag,
+        }
+    }
+}
+
+impl EventHook<RustConn> for IpcHook {
+    fn call(&mut self, event: &XEvent, state: &mut State<RustConn>, x: &RustConn) -> Result<bool> {
+        match event {
+            XEvent::ClientMessage(m) if m.dtype == WAKE_ATOM => {
+                while let Ok(request) = self.requests.try_recv() {
+                    let response = self
+                        .handle(&request.request, state, x)
+                        .unwrap_or_else(|e| Response::error(e.to_string()));
+                    request.reply(response);
+                }
+                Ok(false)
+            }
+            XEvent::PropertyNotify(p)
+                if (p.atom == "WM_NAME" || p.atom == "_NET_WM_NAME")
+                    && ipc::has_subscribers()
+                    && state.client_set.contains(&p.id) =>
+            {
+                ipc::emit(Event::WindowTitle {
+                    id: u64::from(*p.id),
+                    title: x.window_title(p.id).unwrap_or_default(),
+                });
+                Ok(true)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+fn tags(state: &State<RustConn>) -> Vec<Tag> {
+    let cs = &state.client_set;
+    let urgent = urgent_windows(state);
+    let screens: HashMap<&str, usize> = cs
+        .screens()
+        .map(|s| (s.workspace.tag(), s.index()))
+        .collect();
+
+    cs.ordered_tags()
+        .into_iter()
+        .filter_map(|tag| {
+            let ws = cs.workspace(&tag)?;
+            let output = screens.get(tag.as_str()).copied();
+            Some(Tag {
+                focused: tag == cs.current_tag(),
+                visible: output.is_some(),
+                windows: ws.clients().count(),
+                layout: ws.layout_name(),
+                output,
+                urgent: ws.clients().any(|&id| urgent.is_urgent(id)),
+                name: tag,
+            })
+        })
+        .collect()
+}
+
+fn window(id: Xid, tag: &str, state: &State<RustConn>, x: &RustConn) -> Window {
+    // WM_CLASS holds the instance name followed by the class name
+    let class = match x.get_prop(id, "WM_CLASS") {
+        Ok(Some(Prop::UTF8String(strs))) => strs.get(1).cloned().unwrap_or_default(),
+        _ => String::new(),
+    };
+    let fullscreen = match x.get_prop(id, "_NET_WM_STATE") {
+        Ok(Some(Prop::Atom(atoms))) => atoms.iter().any(|a| a == "_NET_WM_STATE_FULLSCREEN"),
+        _ => false,
+    };
+
+    Window {
+        id: u64::from(*id),
+        title: x.window_title(id).unwrap_or_default(),
+        class,
+        tag: tag.to_string(),
+        focused: state.client_set.current_client() == Some(&id),
+        floating: state.client_set.floating.contains_key(&id),
+        fullscreen,
+        urgent: urgent_windows(state).is_urgent(id),
+    }
+}
+
+fn windows(state: &State<RustConn>, x: &RustConn) -> Vec<Window> {
+    let cs = &state.client_set;
+
+    cs.ordered_tags()
+        .iter()
+        .filter_map(|tag| cs.workspace(tag).map(|ws| (tag, ws)))
+        .flat_map(|(tag, ws)| ws.clients().map(move |&id| window(id, tag, state, x)))
+        .collect()
+}
+
+// Penrose only knows the geometry of its screens, the names come from the
+// RandR monitor covering the same area
+fn monitor_names(x: &RustConn) -> Vec<(Rect, String)> {
+    let conn = x.connection();
+    let Ok(Ok(reply)) = conn
+        .randr_get_monitors(*x.root(), true)
+        .map(|cookie| cookie.reply())
+    else {
+        return vec![];
+    };
+
+    reply
+        .monitors
+        .iter()
+        .filter_map(|m| {
+            let name = conn.get_atom_name(m.name).ok()?.reply().ok()?.name;
+            let r = Rect::new(m.x as u32, m.y as u32, m.width as u32, m.height as u32);
+            Some((r, String::from_utf8_lossy(&name).into_owned()))
+        })
+        .collect()
+}
+
+fn outputs(state: &State<RustConn>, x: &RustConn) -> Vec<Output> {
+    let cs = &state.client_set;
+    let names = monitor_names(x);
+
+    cs.screens()
+        .map(|s| {
+            let r = s.geometry();
+            let name = names
+                .iter()
+                .find(|(m, _)| *m == r)
+                .map(|(_, name)| name.clone())
+                .unwrap_or_else(|| s.index().to_string());
+
+            Output {
+                index: s.index(),
+                name,
+                x: r.x as i32,
+                y: r.y as i32,
+                width: r.w,
+                height: r.h,
+                tag: s.workspace.tag().to_string(),
+                focused: s.index() == cs.current_screen().index(),
+            }
+        })
+        .collect()
+}
+
+fn focus(state: &State<RustConn>, x: &RustConn) -> Focus {
+    let cs = &state.client_set;
+
+    Focus {
+        tag: cs.current_tag().to_string(),
+        output: cs.current_screen().index(),
+        window: cs
+            .current_client()
+            .map(|&id| window(id, cs.current_tag(), state, x)),
+    }
+}
+
+// What the client set looked like after the previous refresh
+struct Snapshot {
+    tag: String,
+    focused: Option<Xid>,
+    windows: HashSet<Xid>,
+    layouts: HashMap<String, String>,
+    screens: usize,
+}
+
+impl Snapshot {
+    fn new(state: &State<RustConn>) -> Self {
+        let cs = &state.client_set;
+
+        Self {
+            tag: cs.current_tag().to_string(),
+            focused: cs.current_client().copied(),
+            windows: cs.clients().copied().collect(),
+            layouts: cs
+                .workspaces()
+                .map(|ws| (ws.tag().to_string(), ws.layout_name()))
+                .collect(),
+            screens: cs.screens().count(),
+        }
+    }
+}
+
+// Runs after every refresh and tells subscribers what changed since the last one
+#[derive(Default)]
+pub struct EventsHook {
+    last: Option<Snapshot>,
+}
+
+fn emit_changes(last: &Snapshot, now: &Snapshot, state: &State<RustConn>, x: &RustConn) {
+    let cs = &state.client_set;
+
+    for &id in now.windows.difference(&last.windows) {
+        let tag = cs.tag_for_client(&id).unwrap_or_default();
+        ipc::emit(Event::WindowOpened {
+            window: window(id, tag, state, x),
+        });
+    }
+
+    for id in last.windows.difference(&now.windows) {
+        ipc::emit(Event::WindowClosed {
+            id: u64::from(**id),
+        });
+    }
+
+    if now.tag != last.tag {
+        ipc::emit(Event::TagFocused {
+            tag: now.tag.clone(),
+            output: cs.current_screen().index(),
+        });
+    }
+
+    if now.focused != last.focused {
+        ipc::emit(Event::WindowFocused {
+            window: now.focused.map(|id| window(id, &now.tag, state, x)),
+        });
+    }
+
+    for (tag, layout) in &now.layouts {
+        if last.layouts.get(tag) != Some(layout) {
+            ipc::emit(Event::LayoutChanged {
+                tag: tag.clone(),
+                layout: layout.clone(),
+            });
+        }
+    }
+
+    // Penrose only knows screens by position, so they are added and removed at the end
+    if now.screens > last.screens {
+        for output in outputs(state, x).into_iter().skip(last.screens) {
+            ipc::emit(Event::OutputAdded { output });
+        }
+    }
+    for index in now.screens..last.screens {
+        ipc::emit(Event::OutputRemoved { index });
+    }
+}
+
+impl StateHook<RustConn> for EventsHook {
+    fn call(&mut self, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        let now = Snapshot::new(state);
+
+        if let Some(last) = &self.last {
+            if ipc::has_subscribers() {
+                emit_changes(last, &now, state, x);
+            }
+        }
+
+        self.last = Some(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use penrose::{builtin::actions::key_handler, core::bindings::KeyEventHandler, x11rb::RustConn};
+use std::process::Command;
+use tracing::warn;
+use x11rb::protocol::xkb::{self, ConnectionExt as _};
+
+use crate::{config::Keyboard, supervisor};
+
+// A missing setxkbmap or xset only costs the keyboard settings, sswm still
+// starts without them
+fn run(program: &str, args: &[&str]) {
+    let mut command = Command::new(program);
+    command.args(args);
+
+    if let Err(e) = supervisor::spawn(program, command) {
+        warn!("unable to run {program}: {e}");
+    }
+}
+
+pub fn apply_keyboard_config(keyboard: &Keyboard) {
+    let mut args = vec![];
+
+    for (flag, value) in [
+        ("-rules", &keyboard.rules),
+        ("-model", &keyboard.model),
+        ("-layout", &keyboard.layout),
+        ("-variant", &keyboard.variant),
+    ] {
+        if !value.is_empty() {
+            args.extend([flag, value.as_str()]);
+        }
+    }
+
+    if !keyboard.options.is_empty() {
+        // An empty `-option` clears whatever options were set before ours
+        args.extend(["-option", "", "-option", keyboard.options.as_str()]);
+    }
+
+    if !args.is_empty() {
+        run("setxkbmap", &args);
+    }
+
+    if keyboard.repeat_delay.is_some() || keyboard.repeat_rate.is_some() {
+        let (delay, rate) = keyboard.repeat();
+        run(
+            "xset",
+            &["r", "rate", &delay.to_string(), &rate.to_string()],
+        );
+    }
+}
+
+pub fn switch_keyboard_layout() -> Box<dyn KeyEventHandler<RustConn>> {
+    key_handler(|_, x: &RustConn| {
+        let conn = x.connection();
+        let device_spec = xkb::ID::USE_CORE_KBD.into();
+
+        conn.xkb_use_extension(1, 0)?.reply()?;
+        let num_groups = conn
+            .xkb_get_controls(device_spec)?
+            .reply()?
+            .num_groups
+            .max(1);
+        let locked_group = u8::from(conn.xkb_get_state(device_spec)?.reply()?.locked_group);
+
+        conn.xkb_latch_lock_state(
+            device_spec,
+            0u8,
+            0u8,
+            true,
+            xkb::Group::from((locked_group + 1) % num_groups),
+            0u8,
+            false,
+            0,
+        )?;
+
+        Ok(())
+    })
+}
@@ -0,0 +1,325 @@
+use penrose::{
+    builtin::layout::{
+        messages::{ExpandMain, IncMain, ShrinkMain},
+        transformers::{Gaps, ReflectHorizontal},
+        MainAndStack, Monocle,
+    },
+    core::layout::{Layout, Message},
+    pure::{geometry::Rect, Stack},
+    Xid,
+};
+use tracing::warn;
+
+use crate::{
+    config::{LayoutConfig, TagConfig},
+    x::struts::{AvoidStruts, Struts},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LayoutParams {
+    pub max_main: u32,
+    pub ratio: f32,
+    pub ratio_step: f32,
+    pub outer_gaps: u32,
+    pub inner_gaps: u32,
+    pub top_gaps: u32,
+}
+
+impl LayoutParams {
+    pub fn with_overrides(mut self, cfg: &LayoutConfig) -> Self {
+        self.max_main = cfg.max_main.unwrap_or(self.max_main);
+        self.ratio = cfg.ratio.unwrap_or(self.ratio);
+        self.ratio_step = cfg.ratio_step.unwrap_or(self.ratio_step);
+        self.outer_gaps = cfg.outer_gaps.unwrap_or(self.outer_gaps);
+        self.inner_gaps = cfg.inner_gaps.unwrap_or(self.inner_gaps);
+        self
+    }
+
+    pub fn with_tag_overrides(mut self, cfg: &TagConfig) -> Self {
+        self.max_main = cfg.max_main.unwrap_or(self.max_main);
+        self.ratio = cfg.ratio.unwrap_or(self.ratio);
+        self.outer_gaps = cfg.outer_gaps.unwrap_or(self.outer_gaps);
+        self.inner_gaps = cfg.inner_gaps.unwrap_or(self.inner_gaps);
+        self
+    }
+}
+
+pub fn build_layout(name: &str, p: LayoutParams, struts: &Struts) -> Option<Box<dyn Layout>> {
+    let layout = match name.to_lowercase().as_str() {
+        "side" => MainAndStack::side(p.max_main, p.ratio, p.ratio_step),
+        "sidereflected" => {
+            ReflectHorizontal::wrap(MainAndStack::side(p.max_main, p.ratio, p.ratio_step))
+        }
+        "bottom" => MainAndStack::bottom(p.max_main, p.ratio, p.ratio_step),
+        "monocle" => Monocle::boxed(),
+        "grid" => Box::new(Grid),
+        "centeredmain" => Box::new(CenteredMain {
+            max_main: p.max_main,
+            ratio: p.ratio,
+            ratio_step: p.ratio_step,
+        }),
+        "spiral" => Box::new(Dwindle {
+            ratio: p.ratio,
+            ratio_step: p.ratio_step,
+            spiral: true,
+        }),
+        "dwindle" => Box::new(Dwindle {
+            ratio: p.ratio,
+            ratio_step: p.ratio_step,
+            spiral: false,
+        }),
+        "columns" => Box::new(Lines { columns: true }),
+        "rows" => Box::new(Lines { columns: false }),
+        _ => {
+            warn!("unknown layout: {name}");
+            return None;
+        }
+    };
+
+    Some(AvoidStruts::wrap(
+        Gaps::wrap(layout, p.outer_gaps, p.inner_gaps),
+        p.top_gaps,
+        struts.clone(),
+    ))
+}
+
+fn split_width(r: Rect, ratio: f32) -> (Rect, Rect) {
+    let w = (r.w as f32 * ratio) as u32;
+    (
+        Rect::new(r.x, r.y, w, r.h),
+        Rect::new(r.x + w, r.y, r.w - w, r.h),
+    )
+}
+
+fn split_height(r: Rect, ratio: f32) -> (Rect, Rect) {
+    let h = (r.h as f32 * ratio) as u32;
+    (
+        Rect::new(r.x, r.y, r.w, h),
+        Rect::new(r.x, r.y + h, r.w, r.h - h),
+    )
+}
+
+// The last rect takes whatever is left over from the integer division.
+fn as_rows(r: Rect, n: u32) -> Vec<Rect> {
+    let h = r.h / n.max(1);
+    (0..n)
+        .map(|i| {
+            let y = r.y + i * h;
+            let h = if i + 1 == n { r.h - i * h } else { h };
+            Rect::new(r.x, y, r.w, h)
+        })
+        .collect()
+}
+
+fn as_columns(r: Rect, n: u32) -> Vec<Rect> {
+    let w = r.w / n.max(1);
+    (0..n)
+        .map(|i| {
+            let x = r.x + i * w;
+            let w = if i + 1 == n { r.w - i * w } else { w };
+            Rect::new(x, r.y, w, r.h)
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Grid;
+
+impl Layout for Grid {
+    fn name(&self) -> String {
+        "Grid".into()
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Layout> {
+        Box::new(*self)
+    }
+
+    fn layout(&mut self, s: &Stack<Xid>, r: Rect) -> (Option<Box<dyn Layout>>, Vec<(Xid, Rect)>) {
+        let n = s.len() as u32;
+        let cols = (n as f64).sqrt().ceil() as u32;
+        let rows = as_rows(r, n.div_ceil(cols));
+
+        // The last row spreads its windows over the full width
+        let rects = rows.into_iter().enumerate().flat_map(|(row, rect)| {
+            let in_row = cols.min(n - row as u32 * cols);
+            as_columns(rect, in_row)
+        });
+
+        (None, s.iter().copied().zip(rects).collect())
+    }
+
+    fn handle_message(&mut self, _: &Message) -> Option<Box<dyn Layout>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Lines {
+    columns: bool,
+}
+
+impl Layout for Lines {
+    fn name(&self) -> String {
+        if self.columns {
+            "Columns".into()
+        } else {
+            "Rows".into()
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Layout> {
+        Box::new(*self)
+    }
+
+    fn layout(&mut self, s: &Stack<Xid>, r: Rect) -> (Option<Box<dyn Layout>>, Vec<(Xid, Rect)>) {
+        let n = s.len() as u32;
+        let rects = if self.columns {
+            as_columns(r, n)
+        } else {
+            as_rows(r, n)
+        };
+
+        (None, s.iter().copied().zip(rects).collect())
+    }
+
+    fn handle_message(&mut self, _: &Message) -> Option<Box<dyn Layout>> {
+        None
+    }
+}
+
+fn step_ratio(ratio: &mut f32, step: f32, m: &Message) {
+    if m.downcast_ref::<ExpandMain>().is_some() {
+        *ratio = (*ratio + step).min(1.0 - step);
+    } else if m.downcast_ref::<ShrinkMain>().is_some() {
+        *ratio = (*ratio - step).max(step);
+    }
+}
+
+// Main windows in a centered column with the stack split between the left
+// and right columns, like MainAndStack::side when there is a single stack
+// window.
+#[derive(Debug, Clone, Copy)]
+pub struct CenteredMain {
+    max_main: u32,
+    ratio: f32,
+    ratio_step: f32,
+}
+
+impl Layout for CenteredMain {
+    fn name(&self) -> String {
+        "CenteredMain".into()
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Layout> {
+        Box::new(*self)
+    }
+
+    fn layout(&mut self, s: &Stack<Xid>, r: Rect) -> (Option<Box<dyn Layout>>, Vec<(Xid, Rect)>) {
+        let n = s.len() as u32;
+        let n_main = n.min(self.max_main);
+        let n_stack = n - n_main;
+
+        let rects = if n_main == 0 || n_stack == 0 {
+            as_rows(r, n)
+        } else if n_stack == 1 {
+            let (main, stack) = split_width(r, self.ratio);
+            let mut rects = as_rows(main, n_main);
+            rects.push(stack);
+            rects
+        } else {
+            let side_ratio = (1.0 - self.ratio) / 2.0;
+            let (left, rest) = split_width(r, side_ratio);
+            let (main, right) = split_width(rest, self.ratio / (1.0 - side_ratio));
+
+            let n_right = n_stack.div_ceil(2);
+            let mut right = as_rows(right, n_right).into_iter();
+            let mut left = as_rows(left, n_stack - n_right).into_iter();
+
+            let mut rects = as_rows(main, n_main);
+            for i in 0..n_stack {
+                let next = if i % 2 == 0 {
+                    right.next()
+                } else {
+                    left.next()
+                };
+                rects.extend(next);
+            }
+            rects
+        };
+
+        (None, s.iter().copied().zip(rects).collect())
+    }
+
+    fn handle_message(&mut self, m: &Message) -> Option<Box<dyn Layout>> {
+        if let Some(&IncMain(n)) = m.downcast_ref() {
+            self.max_main = (self.max_main as i32 + n as i32).max(0) as u32;
+        } else {
+            step_ratio(&mut self.ratio, self.ratio_step, m);
+        }
+
+        None
+    }
+}
+
+// Each window takes part of the remaining space and hands the rest on to the
+// next one, alternating between vertical and horizontal splits. The spiral
+// variant rotates which side the window takes so the layout winds inwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Dwindle {
+    ratio: f32,
+    ratio_step: f32,
+    spiral: bool,
+}
+
+impl Layout for Dwindle {
+    fn name(&self) -> String {
+        if self.spiral {
+            "Spiral".into()
+        } else {
+            "Dwindle".into()
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Layout> {
+        Box::new(*self)
+    }
+
+    fn layout(&mut self, s: &Stack<Xid>, r: Rect) -> (Option<Box<dyn Layout>>, Vec<(Xid, Rect)>) {
+        let n = s.len();
+        let mut remaining = r;
+        let mut positions = Vec::with_capacity(n);
+
+        for (i, &id) in s.iter().enumerate() {
+            if i + 1 == n {
+                positions.push((id, remaining));
+                break;
+            }
+
+            let ratio = if i == 0 { self.ratio } else { 0.5 };
+            let turn = if self.spiral { i % 4 } else { i % 2 };
+
+            let (window, rest) = match turn {
+                0 => split_width(remaining, ratio),
+                1 => split_height(remaining, ratio),
+                2 => {
+                    let (rest, window) = split_width(remaining, 1.0 - ratio);
+                    (window, rest)
+                }
+                _ => {
+                    let (rest, window) = split_height(remaining, 1.0 - ratio);
+                    (window, rest)
+                }
+            };
+
+            positions.push((id, window));
+            remaining = rest;
+        }
+
+        (None, positions)
+    }
+
+    fn handle_message(&mut self, m: &Message) -> Option<Box<dyn Layout>> {
+        step_ratio(&mut self.ratio, self.ratio_step, m);
+        None
+    }
+}
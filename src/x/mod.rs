@@ -1 +1,15 @@
+pub mod bar;
+pub mod chords;
+pub mod hooks;
+pub mod ipc;
+pub mod keyboard;
+pub mod layouts;
+pub mod modes;
+pub mod mouse;
+pub mod rules;
+pub mod scratchpad;
+pub mod struts;
+pub mod tags;
+pub mod tray;
+pub mod urgency;
 pub mod xwm;
@@ -0,0 +1,254 @@
+use penrose::{
+    builtin::actions::key_handler,
+    core::{
+        bindings::{keycodes_from_xmodmap, KeyEventHandler},
+        hooks::EventHook,
+        State,
+    },
+    x::{XConn, XEvent},
+    x11rb::RustConn,
+    Result,
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use tracing::warn;
+use x11rb::{
+    connection::Connection,
+    protocol::xproto::{ClientMessageEvent, ConnectionExt as _, EventMask, GrabMode, ModMask},
+};
+
+use crate::x::chords::{parse_key, Key};
+
+pub type KeyBindings = HashMap<String, Box<dyn KeyEventHandler<RustConn>>>;
+
+type Handler = Rc<RefCell<Box<dyn KeyEventHandler<RustConn>>>>;
+
+const MODE_REGRAB: &str = "_SSWM_MODE_REGRAB";
+
+#[derive(Default)]
+struct ModeState {
+    active: Option<String>,
+    // Keys that are only bound inside a mode. Penrose never sees these, they
+    // are grabbed while their mode is active and handled by the hook below.
+    only: HashMap<String, Vec<(String, Handler)>>,
+    // Keysym names to keycodes, read again whenever the keymap changes
+    codes: HashMap<String, u8>,
+    grabbed: Vec<Key>,
+}
+
+impl ModeState {
+    fn keys(&self, mode: &str) -> Vec<Key> {
+        self.only
+            .get(mode)
+            .into_iter()
+            .flatten()
+            .filter_map(|(keybind, _)| parse_key(&self.codes, keybind))
+            .collect()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Modes {
+    state: Rc<RefCell<ModeState>>,
+}
+
+fn grab_keys(x: &RustConn, keys: &[Key], grab: bool) -> Result<()> {
+    let conn = x.connection();
+    let root = *x.root();
+
+    for &(mask, code) in keys {
+        // Penrose doesn't care about NumLock for its own bindings either
+        for mask in [mask, mask | u16::from(ModMask::M2)] {
+            if grab {
+                conn.grab_key(
+                    false,
+                    root,
+                    mask.into(),
+                    code,
+                    GrabMode::ASYNC,
+                    GrabMode::ASYNC,
+                )?;
+            } else {
+                conn.ungrab_key(code, root, mask.into())?;
+            }
+        }
+    }
+
+    conn.flush()?;
+    Ok(())
+}
+
+// Penrose regrabs its own bindings after the event hooks have run, so the
+// mode keys are grabbed again once it is done with the mapping change
+fn request_regrab(x: &RustConn) -> Result<()> {
+    let conn = x.connection();
+    let root = *x.root();
+    let atom = *x.intern_atom(MODE_REGRAB)?;
+
+    let message = ClientMessageEvent::new(32, root, atom, [0u32; 5]);
+    conn.send_event(
+        false,
+        root,
+        EventMask::SUBSTRUCTURE_NOTIFY | EventMask::SUBSTRUCTURE_REDIRECT,
+        message,
+    )?;
+    conn.flush()?;
+    Ok(())
+}
+
+impl Modes {
+    fn set(&self, x: &RustConn, mode: Option<String>) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+
+        if let Some(name) = mode.as_ref().filter(|m| !state.only.contains_key(*m)) {
+            warn!("unknown mode: {name}");
+            return Ok(());
+        }
+
+        grab_keys(x, &std::mem::take(&mut state.grabbed), false)?;
+        if let Some(name) = mode.as_ref() {
+            state.grabbed = state.keys(name);
+            grab_keys(x, &state.grabbed, true)?;
+        }
+
+        state.active = mode;
+        Ok(())
+    }
+
+    fn regrab(&self, x: &RustConn) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.codes = keycodes_from_xmodmap()?;
+
+        if let Some(name) = state.active.clone() {
+            grab_keys(x, &std::mem::take(&mut state.grabbed), false)?;
+            state.grabbed = state.keys(&name);
+            grab_keys(x, &state.grabbed, true)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn enter(&self, name: &str) -> Box<dyn KeyEventHandler<RustConn>> {
+        let modes = self.clone();
+        let name = name.to_string();
+        key_handler(move |_, x: &RustConn| modes.set(x, Some(name.clone())))
+    }
+
+    pub fn exit(&self) -> Box<dyn KeyEventHandler<RustConn>> {
+        let modes = self.clone();
+        key_handler(move |_, x: &RustConn| modes.set(x, None))
+    }
+
+    // Keys that also have a normal binding are routed through a ModalKey that
+    // picks the handler for the active mode, the rest are kept here.
+    pub fn wrap(
+        &self,
+        normal: KeyBindings,
+        modes: Vec<(String, KeyBindings)>,
+    ) -> Result<KeyBindings> {
+        if modes.is_empty() {
+            return Ok(normal);
+        }
+
+        let mut keys: HashMap<String, ModalKey> = normal
+            .into_iter()
+            .map(|(keybind, handler)| (keybind, ModalKey::new(self, handler)))
+            .collect();
+        let mut state = self.state.borrow_mut();
+        state.codes = keycodes_from_xmodmap()?;
+
+        for (name, bindings) in modes {
+            let mut only = vec![];
+
+            for (keybind, handler) in bindings {
+                if let Some(key) = keys.get_mut(&keybind) {
+                    key.in_mode.insert(name.clone(), handler);
+                } else if parse_key(&state.codes, &keybind).is_some() {
+                    only.push((keybind, Rc::new(RefCell::new(handler))));
+                } else {
+                    warn!("invalid key binding in mode {name}: {keybind}");
+                }
+            }
+
+            state.only.insert(name, only);
+        }
+
+        Ok(keys
+            .into_iter()
+            .map(|(keybind, key)| (keybind, Box::new(key) as Box<dyn KeyEventHandler<RustConn>>))
+            .collect())
+    }
+}
+
+impl EventHook<RustConn> for Modes {
+    fn call(&mut self, event: &XEvent, state: &mut State<RustConn>, x: &RustConn) -> Result<bool> {
+        match event {
+            XEvent::KeyPress(k) => {
+                let mods = u16::from(ModMask::SHIFT | ModMask::CONTROL | ModMask::M1 | ModMask::M4);
+                let key = (k.mask & mods, k.code);
+
+                let handler = {
+                    let modes = self.state.borrow();
+                    let Some(active) = modes.active.as_ref() else {
+                        return Ok(true);
+                    };
+                    modes
+                        .only
+                        .get(active)
+                        .into_iter()
+                        .flatten()
+                        .find(|(keybind, _)| parse_key(&modes.codes, keybind) == Some(key))
+                        .map(|(_, handler)| handler.clone())
+                };
+
+                // The handler may well leave the mode, so the state is released first
+                match handler {
+                    Some(handler) => {
+                        handler.borrow_mut().call(state, x)?;
+                        Ok(false)
+                    }
+                    None => Ok(true),
+                }
+            }
+            XEvent::MappingNotify => {
+                request_regrab(x)?;
+                Ok(true)
+            }
+            XEvent::ClientMessage(m) if m.dtype == MODE_REGRAB => {
+                self.regrab(x)?;
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+struct ModalKey {
+    modes: Modes,
+    normal: Box<dyn KeyEventHandler<RustConn>>,
+    in_mode: HashMap<String, Box<dyn KeyEventHandler<RustConn>>>,
+}
+
+impl ModalKey {
+    fn new(modes: &Modes, normal: Box<dyn KeyEventHandler<RustConn>>) -> Self {
+        Self {
+            modes: modes.clone(),
+            normal,
+            in_mode: HashMap::new(),
+        }
+    }
+}
+
+impl KeyEventHandler<RustConn> for ModalKey {
+    fn call(&mut self, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        let active = self.modes.state.borrow().active.clone();
+
+        // Keys without a handler in the active mode do nothing
+        match active {
+            Some(mode) => match self.in_mode.get_mut(&mode) {
+                Some(handler) => handler.call(state, x),
+                None => Ok(()),
+            },
+            None => self.normal.call(state, x),
+        }
+    }
+}
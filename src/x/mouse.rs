@@ -0,0 +1,244 @@
+use penrose::{
+    core::{
+        bindings::{
+            ModifierKey, MouseBindings, MouseButton, MouseEvent, MouseEventHandler, MouseEventKind,
+            MouseState,
+        },
+        State,
+    },
+    pure::geometry::{Point, Rect},
+    x::{XConn, XConnExt},
+    x11rb::RustConn,
+    Result, Xid,
+};
+use std::{cell::RefCell, rc::Rc};
+use tracing::warn;
+
+use crate::config::MouseBinding;
+
+struct Drag {
+    client: Xid,
+    start: Point,
+    geometry: Rect,
+}
+
+// Press, motion and release are separate bindings in penrose, so the three
+// handlers for one button share the drag they are working on.
+#[derive(Clone)]
+struct DragHandler {
+    resize: bool,
+    float_tiled: bool,
+    drag: Rc<RefCell<Option<Drag>>>,
+}
+
+impl DragHandler {
+    fn rect_for(&self, drag: &Drag, p: Point) -> Rect {
+        let dx = p.x as i32 - drag.start.x as i32;
+        let dy = p.y as i32 - drag.start.y as i32;
+        let r = drag.geometry;
+
+        if self.resize {
+            Rect::new(
+                r.x,
+                r.y,
+                (r.w as i32 + dx).max(1) as u32,
+                (r.h as i32 + dy).max(1) as u32,
+            )
+        } else {
+            Rect::new(
+                (r.x as i32 + dx).max(0) as u32,
+                (r.y as i32 + dy).max(0) as u32,
+                r.w,
+                r.h,
+            )
+        }
+    }
+}
+
+impl<X> MouseEventHandler<X> for DragHandler
+where
+    X: XConn,
+{
+    fn call(&mut self, evt: &MouseEvent, state: &mut State<X>, x: &X) -> Result<()> {
+        match evt.kind {
+            MouseEventKind::Press => {
+                let id = evt.data.id;
+                if !state.client_set.contains(&id) {
+                    return Ok(());
+                }
+
+                let floating = state.client_set.floating.contains_key(&id);
+                if !floating && !self.float_tiled {
+                    return Ok(());
+                }
+
+                let geometry = x.client_geometry(id)?;
+                if !floating {
+                    state.client_set.float(id, geometry)?;
+                }
+                x.modify_and_refresh(state, |cs| cs.focus_client(&id))?;
+
+                *self.drag.borrow_mut() = Some(Drag {
+                    client: id,
+                    start: evt.data.rpt,
+                    geometry,
+                });
+            }
+
+            // Only the window is moved while dragging, the layout is left alone
+            // until the button is released.
+            MouseEventKind::Motion => {
+                if let Some(drag) = self.drag.borrow().as_ref() {
+                    let r = self.rect_for(drag, evt.data.rpt);
+                    x.position_client(drag.client, r)?;
+                }
+            }
+
+            MouseEventKind::Release => {
+                if let Some(drag) = self.drag.borrow_mut().take() {
+                    let r = self.rect_for(&drag, evt.data.rpt);
+                    state.client_set.float(drag.client, r)?;
+                    x.refresh(state)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct ClickHandler<F>(F);
+
+impl<F, X> MouseEventHandler<X> for ClickHandler<F>
+where
+    F: FnMut(Xid, &mut State<X>, &X) -> Result<()>,
+    X: XConn,
+{
+    fn call(&mut self, evt: &MouseEvent, state: &mut State<X>, x: &X) -> Result<()> {
+        (self.0)(evt.data.id, state, x)
+    }
+}
+
+fn click<F>(f: F) -> Box<dyn MouseEventHandler<RustConn>>
+where
+    F: FnMut(Xid, &mut State<RustConn>, &RustConn) -> Result<()> + 'static,
+{
+    Box::new(ClickHandler(f))
+}
+
+fn cycle_tag<X: XConn>(state: &mut State<X>, x: &X, forward: bool) -> Result<()> {
+    let tags = state.client_set.ordered_tags();
+    let current = state.client_set.current_tag().to_string();
+    let Some(i) = tags.iter().position(|t| t == &current) else {
+        return Ok(());
+    };
+
+    let next = if forward {
+        (i + 1) % tags.len()
+    } else {
+        (i + tags.len() - 1) % tags.len()
+    };
+    let tag = tags[next].clone();
+
+    x.modify_and_refresh(state, |cs| cs.focus_tag(&tag))
+}
+
+// The same modifier letters as key bindings, followed by the button name,
+// e.g. `M-S-Left` or `M-ScrollUp`.
+fn parse_mousebind(bind: &str) -> Option<MouseState> {
+    let mut parts: Vec<&str> = bind.split('-').collect();
+    let button = match parts.pop()?.to_lowercase().as_str() {
+        "left" => MouseButton::Left,
+        "middle" => MouseButton::Middle,
+        "right" => MouseButton::Right,
+        "scrollup" => MouseButton::ScrollUp,
+        "scrolldown" => MouseButton::ScrollDown,
+        _ => return None,
+    };
+
+    let mut modifiers = vec![];
+    for m in parts {
+        modifiers.push(match m {
+            "M" => ModifierKey::Meta,
+            "A" => ModifierKey::Alt,
+            "C" => ModifierKey::Ctrl,
+            "S" => ModifierKey::Shift,
+            _ => return None,
+        });
+    }
+
+    Some(MouseState::new(button, modifiers))
+}
+
+fn drag_bindings(
+    bindings: &mut MouseBindings<RustConn>,
+    mouse_state: MouseState,
+    resize: bool,
+    float_tiled: bool,
+) {
+    let handler = DragHandler {
+        resize,
+        float_tiled,
+        drag: Rc::new(RefCell::new(None)),
+    };
+
+    for kind in [
+        MouseEventKind::Press,
+        MouseEventKind::Motion,
+        MouseEventKind::Release,
+    ] {
+        bindings.insert((kind, mouse_state.clone()), Box::new(handler.clone()));
+    }
+}
+
+pub fn mouse_bindings(binds: &[MouseBinding]) -> MouseBindings<RustConn> {
+    let mut bindings = MouseBindings::new();
+
+    for b in binds {
+        let Some(mouse_state) = parse_mousebind(&b.mousebind) else {
+            warn!("invalid mouse binding: {}", b.mousebind);
+            continue;
+        };
+
+        let handler = match b.action.to_lowercase().as_str() {
+            "move" => {
+                drag_bindings(&mut bindings, mouse_state, false, true);
+                continue;
+            }
+            "resize" => {
+                drag_bindings(&mut bindings, mouse_state, true, true);
+                continue;
+            }
+            "movefloating" => {
+                drag_bindings(&mut bindings, mouse_state, false, false);
+                continue;
+            }
+            "resizefloating" => {
+                drag_bindings(&mut bindings, mouse_state, true, false);
+                continue;
+            }
+            "focus" => click(|id, state, x| x.modify_and_refresh(state, |cs| cs.focus_client(&id))),
+            "kill" => click(|id, state, x| {
+                x.modify_and_refresh(state, |cs| {
+                    cs.focus_client(&id);
+                    cs.kill_focused();
+                })
+            }),
+            "sink" => click(|id, state, x| {
+                x.modify_and_refresh(state, |cs| {
+                    cs.sink(&id);
+                })
+            }),
+            "nexttag" => click(|_, state, x| cycle_tag(state, x, true)),
+            "previoustag" => click(|_, state, x| cycle_tag(state, x, false)),
+            _ => {
+                warn!("unknown mouse action: {}", b.action);
+                continue;
+            }
+        };
+
+        bindings.insert((MouseEventKind::Press, mouse_state), handler);
+    }
+
+    bindings
+}
@@ -0,0 +1,177 @@
+use penrose::{
+    core::{
+        hooks::{EventHook, ManageHook},
+        State,
+    },
+    x::{property::Prop, Query, XConn, XEvent},
+    x11rb::RustConn,
+    Result, Xid,
+};
+use regex::Regex;
+use tracing::warn;
+
+use crate::{
+    config::{FocusStealing, Rule},
+    x::{
+        hooks::{FloatRelative, WindowQuery},
+        urgency::mark_urgent,
+    },
+};
+
+struct WindowRule {
+    query: WindowQuery,
+    rule: Rule,
+}
+
+fn window_rules(rules: &[Rule]) -> Vec<WindowRule> {
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let title = match rule.title.as_deref().map(Regex::new) {
+                Some(Ok(title)) => Some(title),
+                Some(Err(e)) => {
+                    warn!("skipping rule with invalid title regex: {e}");
+                    return None;
+                }
+                None => None,
+            };
+
+            Some(WindowRule {
+                query: WindowQuery {
+                    class: rule.class.clone(),
+                    instance: rule.instance.clone(),
+                    title,
+                    window_type: rule.window_type.clone(),
+                    role: rule.role.clone(),
+                },
+                rule: rule.clone(),
+            })
+        })
+        .collect()
+}
+
+pub struct RulesHook {
+    rules: Vec<WindowRule>,
+}
+
+impl RulesHook {
+    pub fn new(rules: &[Rule]) -> Self {
+        Self {
+            rules: window_rules(rules),
+        }
+    }
+}
+
+// Every matching rule is applied in order, so later rules override earlier
+// ones, except `ignore` which stops managing the window right away.
+impl<X> ManageHook<X> for RulesHook
+where
+    X: XConn,
+{
+    fn call(&mut self, client: Xid, state: &mut State<X>, x: &X) -> Result<()> {
+        for WindowRule { query, rule } in &self.rules {
+            if !query.run(client, x)? {
+                continue;
+            }
+
+            if rule.ignore {
+                state.client_set.remove_client(&client);
+                return x.map(client);
+            }
+
+            if let Some(tag) = &rule.tag {
+                state.client_set.move_client_to_tag(&client, tag);
+            }
+
+            if rule.fullscreen {
+                let r = state.client_set.current_screen().geometry();
+                state.client_set.float(client, r)?;
+                x.set_prop(
+                    client,
+                    "_NET_WM_STATE",
+                    Prop::Atom(vec!["_NET_WM_STATE_FULLSCREEN".to_string()]),
+                )?;
+            } else if rule.floating {
+                let r = match (rule.x, rule.y, rule.width, rule.height) {
+                    (Some(x), Some(y), Some(w), Some(h)) => FloatRelative { x, y, w, h }
+                        .rect_in(state.client_set.current_screen().geometry()),
+                    _ => x.client_geometry(client)?,
+                };
+                state.client_set.float(client, r)?;
+            }
+
+            match rule.focus {
+                Some(true) => state.client_set.focus_client(&client),
+                // New clients are inserted above the focused one and take
+                // focus, so stepping down gives it back to the previous one
+                Some(false) if state.client_set.current_client() == Some(&client) => {
+                    state.client_set.focus_down()
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Decides what a _NET_ACTIVE_WINDOW request from a client gets before the
+// EWMH hook focuses the window. The last matching rule with a
+// `focus_stealing` override wins over the configured policy.
+pub struct FocusStealingHook {
+    policy: FocusStealing,
+    rules: Vec<WindowRule>,
+}
+
+impl FocusStealingHook {
+    pub fn new(policy: FocusStealing, rules: &[Rule]) -> Self {
+        let rules = window_rules(rules)
+            .into_iter()
+            .filter(|r| r.rule.focus_stealing.is_some())
+            .collect();
+
+        Self { policy, rules }
+    }
+
+    fn policy_for(&self, client: Xid, x: &RustConn) -> Result<FocusStealing> {
+        let mut policy = self.policy;
+        for WindowRule { query, rule } in &self.rules {
+            if query.run(client, x)? {
+                policy = rule.focus_stealing.unwrap_or(policy);
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+impl EventHook<RustConn> for FocusStealingHook {
+    fn call(&mut self, event: &XEvent, state: &mut State<RustConn>, x: &RustConn) -> Result<bool> {
+        let XEvent::ClientMessage(m) = event else {
+            return Ok(true);
+        };
+        if m.dtype != "_NET_ACTIVE_WINDOW" || !state.client_set.contains(&m.id) {
+            return Ok(true);
+        }
+
+        // Pagers and window switchers say the request comes from the user
+        if m.data().as_usize()[0] == 2 {
+            return Ok(true);
+        }
+
+        let allowed = match self.policy_for(m.id, x)? {
+            FocusStealing::Allow => true,
+            FocusStealing::CurrentTag => {
+                let cs = &state.client_set;
+                cs.tag_for_client(&m.id) == Some(cs.current_tag())
+            }
+            FocusStealing::Urgent => {
+                mark_urgent(m.id, state, x)?;
+                false
+            }
+            FocusStealing::Deny => false,
+        };
+
+        Ok(allowed)
+    }
+}
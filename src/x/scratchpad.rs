@@ -0,0 +1,119 @@
+use penrose::{
+    core::{bindings::KeyEventHandler, hooks::ManageHook, State},
+    extensions::hooks::named_scratchpads::{NamedScratchPad, ToggleNamedScratchPad},
+    x::Query,
+    x11rb::RustConn,
+    Result, Xid,
+};
+use regex::Regex;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    config::{Command, Scratchpad},
+    spawn::spawn,
+    x::hooks::{FloatRelative, WindowQuery},
+};
+
+// Penrose would start the program itself by splitting the command on
+// whitespace. It gets a no-op instead and the toggle below starts the real
+// command, with its shell, env and cwd, whenever no window is recorded.
+const NO_COMMAND: &str = "true";
+
+// The window each scratchpad owns, claimed the way penrose's own manage hook
+// does it: the first matching window to be managed while there is none. The
+// toggle only hands over to penrose once there is one, so penrose never has
+// to start anything.
+#[derive(Clone, Default)]
+pub struct ScratchpadClients {
+    pads: Rc<RefCell<Vec<(WindowQuery, Option<Xid>)>>>,
+}
+
+impl ManageHook<RustConn> for ScratchpadClients {
+    fn call(&mut self, id: Xid, _state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        for (query, client) in self.pads.borrow_mut().iter_mut() {
+            if client.is_none() && query.run(id, x)? {
+                *client = Some(id);
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ToggleScratchpad {
+    toggle: ToggleNamedScratchPad,
+    clients: ScratchpadClients,
+    index: usize,
+    command: Command,
+}
+
+impl KeyEventHandler<RustConn> for ToggleScratchpad {
+    fn call(&mut self, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        let client = {
+            let mut pads = self.clients.pads.borrow_mut();
+            let client = &mut pads[self.index].1;
+            // Closed since, the next window it opens is claimed instead
+            if client.is_some_and(|id| !state.client_set.contains(&id)) {
+                *client = None;
+            }
+            *client
+        };
+
+        match client {
+            Some(_) => self.toggle.call(state, x),
+            None => {
+                spawn(&self.command);
+                Ok(())
+            }
+        }
+    }
+}
+
+pub fn named_scratchpads(
+    scratchpads: &[Scratchpad],
+) -> (
+    Vec<NamedScratchPad<RustConn>>,
+    HashMap<String, ToggleScratchpad>,
+    ScratchpadClients,
+) {
+    let mut nsps = vec![];
+    let mut toggles = HashMap::new();
+    let clients = ScratchpadClients::default();
+
+    for (index, sp) in scratchpads.iter().enumerate() {
+        let query = WindowQuery {
+            class: sp.class.clone(),
+            instance: sp.instance.clone(),
+            title: sp
+                .title
+                .as_ref()
+                .and_then(|t| Regex::new(&format!("^{}$", regex::escape(t))).ok()),
+            ..Default::default()
+        };
+
+        let float = FloatRelative {
+            x: sp.x,
+            y: sp.y,
+            w: sp.width,
+            h: sp.height,
+        };
+
+        let (nsp, toggle) =
+            NamedScratchPad::new(sp.name.clone(), NO_COMMAND, query.clone(), float, true);
+        nsps.push(nsp);
+        clients.pads.borrow_mut().push((query, None));
+        toggles.insert(
+            sp.name.clone(),
+            ToggleScratchpad {
+                toggle,
+                clients: clients.clone(),
+                index,
+                command: sp.command(),
+            },
+        );
+    }
+
+    (nsps, toggles, clients)
+}
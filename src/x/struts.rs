@@ -0,0 +1,326 @@
+use penrose::{
+    builtin::actions::key_handler,
+    core::{
+        bindings::KeyEventHandler,
+        hooks::{EventHook, ManageHook},
+        layout::{Layout, Message},
+        State,
+    },
+    pure::{geometry::Rect, Stack},
+    x::{property::Prop, XConn, XConnExt, XEvent},
+    x11rb::RustConn,
+    Error, Result, Xid,
+};
+use std::sync::{Arc, Mutex};
+use x11rb::protocol::xproto::{ChangeWindowAttributesAux, ConnectionExt as _, EventMask};
+
+// Left to place themselves, docks also reserve space with their struts
+const UNMANAGED_TYPES: &[&str] = &[
+    "_NET_WM_WINDOW_TYPE_DOCK",
+    "_NET_WM_WINDOW_TYPE_SPLASH",
+    "_NET_WM_WINDOW_TYPE_NOTIFICATION",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+// The area a dock takes up along one edge of the root window
+#[derive(Debug, Clone, Copy)]
+struct Strut {
+    side: Side,
+    area: Rect,
+}
+
+#[derive(Debug)]
+struct Reserved {
+    docks: Vec<(Xid, Vec<Strut>)>,
+    avoid: bool,
+}
+
+// The struts of every dock. Kept as a state extension, with a clone in each
+// layout, which runs without access to the state.
+#[derive(Debug, Clone)]
+pub struct Struts(Arc<Mutex<Reserved>>);
+
+impl Default for Struts {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Reserved {
+            docks: vec![],
+            avoid: true,
+        })))
+    }
+}
+
+impl Struts {
+    fn set(&self, id: Xid, struts: Vec<Strut>) {
+        let mut all = self.0.lock().unwrap();
+        all.docks.retain(|(dock, _)| *dock != id);
+        all.docks.push((id, struts));
+    }
+
+    fn forget(&self, id: Xid) -> bool {
+        let mut all = self.0.lock().unwrap();
+        let before = all.docks.len();
+        all.docks.retain(|(dock, _)| *dock != id);
+        all.docks.len() != before
+    }
+
+    fn is_tracked(&self, id: Xid) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .docks
+            .iter()
+            .any(|(dock, _)| *dock == id)
+    }
+
+    fn toggle(&self) {
+        let mut all = self.0.lock().unwrap();
+        all.avoid = !all.avoid;
+    }
+
+    // Struts are measured from the edge of the root window, so one reserving
+    // the left edge of the right monitor covers all of the left monitor too.
+    // Each is cut down to the screen first, and one that would leave nothing
+    // of it isn't meant for it.
+    fn avoid(&self, r: Rect) -> Option<Rect> {
+        let all = self.0.lock().unwrap();
+        if !all.avoid {
+            return None;
+        }
+
+        let (mut left, mut right, mut top, mut bottom) = (0, 0, 0, 0);
+
+        for &Strut { side, area } in all.docks.iter().flat_map(|(_, s)| s) {
+            let Some(s) = intersection(area, r) else {
+                continue;
+            };
+
+            match side {
+                Side::Left if s.w < r.w => left = left.max(s.x + s.w - r.x),
+                Side::Right if s.w < r.w => right = right.max(r.x + r.w - s.x),
+                Side::Top if s.h < r.h => top = top.max(s.y + s.h - r.y),
+                Side::Bottom if s.h < r.h => bottom = bottom.max(r.y + r.h - s.y),
+                _ => {}
+            }
+        }
+
+        Some(Rect::new(
+            r.x + left,
+            r.y + top,
+            r.w.saturating_sub(left + right),
+            r.h.saturating_sub(top + bottom),
+        ))
+    }
+}
+
+fn intersection(a: Rect, b: Rect) -> Option<Rect> {
+    let (x, y) = (a.x.max(b.x), a.y.max(b.y));
+    let (x1, y1) = ((a.x + a.w).min(b.x + b.w), (a.y + a.h).min(b.y + b.h));
+
+    (x < x1 && y < y1).then(|| Rect::new(x, y, x1 - x, y1 - y))
+}
+
+// Empty if the extension was never added
+fn struts<X: XConn>(state: &State<X>) -> Struts {
+    state
+        .extension::<Struts>()
+        .map(|s| s.borrow().clone())
+        .unwrap_or_default()
+}
+
+fn x_err(e: impl std::fmt::Display) -> Error {
+    Error::Custom(e.to_string())
+}
+
+fn window_types(id: Xid, x: &RustConn) -> Vec<String> {
+    match x.get_prop(id, "_NET_WM_WINDOW_TYPE") {
+        Ok(Some(Prop::Atom(types))) => types,
+        _ => vec![],
+    }
+}
+
+fn is_unmanaged(id: Xid, x: &RustConn) -> bool {
+    window_types(id, x)
+        .iter()
+        .any(|t| UNMANAGED_TYPES.contains(&t.as_str()))
+}
+
+fn is_dock(id: Xid, x: &RustConn) -> bool {
+    window_types(id, x)
+        .iter()
+        .any(|t| t == "_NET_WM_WINDOW_TYPE_DOCK")
+}
+
+// _NET_WM_STRUT_PARTIAL says which part of each edge is covered, the older
+// _NET_WM_STRUT always covers the whole edge
+fn read_struts(id: Xid, x: &RustConn) -> Result<Vec<Strut>> {
+    let root = x.client_geometry(x.root())?;
+
+    let values = match x.get_prop(id, "_NET_WM_STRUT_PARTIAL")? {
+        Some(Prop::Cardinal(values)) if values.len() >= 12 => values,
+        _ => match x.get_prop(id, "_NET_WM_STRUT")? {
+            Some(Prop::Cardinal(v)) if v.len() >= 4 => {
+                let (h, w) = (root.h.saturating_sub(1), root.w.saturating_sub(1));
+                vec![v[0], v[1], v[2], v[3], 0, h, 0, h, 0, w, 0, w]
+            }
+            _ => return Ok(vec![]),
+        },
+    };
+
+    let &[left, right, top, bottom, ly0, ly1, ry0, ry1, tx0, tx1, bx0, bx1] = &values[..12] else {
+        return Ok(vec![]);
+    };
+    let len = |start: u32, end: u32| end.saturating_sub(start) + 1;
+
+    let struts = [
+        (Side::Left, Rect::new(0, ly0, left, len(ly0, ly1))),
+        (
+            Side::Right,
+            Rect::new(root.w.saturating_sub(right), ry0, right, len(ry0, ry1)),
+        ),
+        (Side::Top, Rect::new(tx0, 0, len(tx0, tx1), top)),
+        (
+            Side::Bottom,
+            Rect::new(bx0, root.h.saturating_sub(bottom), len(bx0, bx1), bottom),
+        ),
+    ];
+
+    Ok(struts
+        .into_iter()
+        .filter(|(_, area)| area.w > 0 && area.h > 0)
+        .map(|(side, area)| Strut { side, area })
+        .collect())
+}
+
+// Docks aren't clients, so their property and structure changes have to be
+// asked for separately
+fn track(id: Xid, struts: &Struts, x: &RustConn) -> Result<()> {
+    if !is_dock(id, x) {
+        return Ok(());
+    }
+
+    x.connection()
+        .change_window_attributes(
+            *id,
+            &ChangeWindowAttributesAux::new()
+                .event_mask(EventMask::PROPERTY_CHANGE | EventMask::STRUCTURE_NOTIFY),
+        )
+        .map_err(x_err)?;
+
+    struts.set(id, read_struts(id, x)?);
+    Ok(())
+}
+
+pub fn toggle_struts() -> Box<dyn KeyEventHandler<RustConn>> {
+    key_handler(|state, x| {
+        struts(state).toggle();
+        x.refresh(state)
+    })
+}
+
+// Takes the dock struts of whichever screen is being laid out and the fixed
+// `top_gaps` off the area the wrapped layout gets
+pub struct AvoidStruts {
+    inner: Box<dyn Layout>,
+    top_gaps: u32,
+    struts: Struts,
+}
+
+impl AvoidStruts {
+    pub fn wrap(inner: Box<dyn Layout>, top_gaps: u32, struts: Struts) -> Box<dyn Layout> {
+        Box::new(Self {
+            inner,
+            top_gaps,
+            struts,
+        })
+    }
+}
+
+impl Layout for AvoidStruts {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn boxed_clone(&self) -> Box<dyn Layout> {
+        Self::wrap(self.inner.boxed_clone(), self.top_gaps, self.struts.clone())
+    }
+
+    fn layout(&mut self, s: &Stack<Xid>, r: Rect) -> (Option<Box<dyn Layout>>, Vec<(Xid, Rect)>) {
+        let r = match self.struts.avoid(r) {
+            Some(r) => Rect::new(
+                r.x,
+                r.y + self.top_gaps,
+                r.w,
+                r.h.saturating_sub(self.top_gaps),
+            ),
+            None => r,
+        };
+
+        let (new, positions) = self.inner.layout(s, r);
+        if let Some(new) = new {
+            self.inner = new;
+        }
+
+        (None, positions)
+    }
+
+    fn handle_message(&mut self, m: &Message) -> Option<Box<dyn Layout>> {
+        if let Some(new) = self.inner.handle_message(m) {
+            self.inner = new;
+        }
+
+        None
+    }
+}
+
+// Docks, splash screens and notifications are mapped as they are instead of
+// being managed, and docks are followed for their struts.
+pub struct Docks;
+
+impl EventHook<RustConn> for Docks {
+    fn call(&mut self, event: &XEvent, state: &mut State<RustConn>, x: &RustConn) -> Result<bool> {
+        match event {
+            XEvent::MapRequest(id) if !state.client_set.contains(id) && is_unmanaged(*id, x) => {
+                track(*id, &struts(state), x)?;
+                x.map(*id)?;
+                x.refresh(state)?;
+                Ok(false)
+            }
+            XEvent::Destroy(id) | XEvent::UnmapNotify(id) => {
+                if struts(state).forget(*id) {
+                    x.refresh(state)?;
+                }
+                Ok(true)
+            }
+            XEvent::PropertyNotify(p)
+                if p.atom.starts_with("_NET_WM_STRUT") && struts(state).is_tracked(p.id) =>
+            {
+                track(p.id, &struts(state), x)?;
+                x.refresh(state)?;
+                Ok(true)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+// New windows never get this far, but the ones that were already there when
+// sswm started are managed without a map request. Like an ignore rule, this
+// hands them back before the manage refresh lays anything out.
+impl ManageHook<RustConn> for Docks {
+    fn call(&mut self, id: Xid, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        if !is_unmanaged(id, x) {
+            return Ok(());
+        }
+
+        state.client_set.remove_client(&id);
+        track(id, &struts(state), x)?;
+        x.map(id)
+    }
+}
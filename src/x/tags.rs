@@ -0,0 +1,75 @@
+use penrose::{
+    core::{
+        hooks::{ManageHook, StateHook},
+        layout::LayoutStack,
+        State,
+    },
+    pure::Position,
+    x::XConn,
+    Result, Xid,
+};
+use std::collections::HashMap;
+
+use crate::config::{NewWindow, TagConfig};
+
+// Every workspace starts with a clone of `default_layouts`, so tags with their
+// own settings get their layout stack swapped in once on startup.
+pub struct TagLayouts {
+    layouts: Vec<(String, LayoutStack)>,
+}
+
+impl TagLayouts {
+    pub fn new(layouts: Vec<(String, LayoutStack)>) -> Self {
+        Self { layouts }
+    }
+}
+
+impl<X> StateHook<X> for TagLayouts
+where
+    X: XConn,
+{
+    fn call(&mut self, state: &mut State<X>, _x: &X) -> Result<()> {
+        for (tag, layouts) in self.layouts.drain(..) {
+            if let Some(ws) = state.client_set.workspace_mut(&tag) {
+                ws.set_available_layouts(layouts);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct NewWindowPosition {
+    positions: HashMap<String, NewWindow>,
+}
+
+impl NewWindowPosition {
+    pub fn new(tags: &[TagConfig]) -> Self {
+        Self {
+            positions: tags
+                .iter()
+                .filter_map(|t| t.new_window.map(|n| (t.tag.clone(), n)))
+                .collect(),
+        }
+    }
+}
+
+impl<X> ManageHook<X> for NewWindowPosition
+where
+    X: XConn,
+{
+    fn call(&mut self, client: Xid, state: &mut State<X>, _x: &X) -> Result<()> {
+        let position = match self.positions.get(state.client_set.current_tag()) {
+            Some(NewWindow::Main) => Position::Head,
+            Some(NewWindow::Stack) => Position::Tail,
+            None => return Ok(()),
+        };
+
+        if state.client_set.current_client() == Some(&client) {
+            state.client_set.remove_client(&client);
+            state.client_set.insert_at(position, client);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+use penrose::{core::State, pure::geometry::Rect, x11rb::RustConn, Color, Error, Result};
+use penrose_ui::{bar::widgets::Widget, core::Context};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+};
+use tracing::{error, info};
+use x11rb::{
+    atom_manager,
+    connection::Connection,
+    protocol::{
+        xproto::{
+            AtomEnum, ChangeWindowAttributesAux, ClientMessageEvent, ConfigureWindowAux,
+            ConnectionExt as _, CreateWindowAux, EventMask, PropMode, SetMode, StackMode, Window,
+            WindowClass,
+        },
+        Event,
+    },
+    wrapper::ConnectionExt as _,
+    COPY_DEPTH_FROM_PARENT, CURRENT_TIME,
+};
+
+use crate::config::Bar;
+
+const SYSTEM_TRAY_REQUEST_DOCK: u32 = 0;
+const XEMBED_EMBEDDED_NOTIFY: u32 = 0;
+const XEMBED_VERSION: u32 = 0;
+const XEMBED_MAPPED: u32 = 1;
+
+atom_manager! {
+    Atoms: AtomsCookie {
+        MANAGER,
+        _NET_SYSTEM_TRAY_OPCODE,
+        _NET_SYSTEM_TRAY_ORIENTATION,
+        _XEMBED,
+        _XEMBED_INFO,
+    }
+}
+
+fn x_err(e: impl std::fmt::Display) -> Error {
+    Error::Custom(e.to_string())
+}
+
+struct Icon {
+    window: Window,
+    mapped: bool,
+}
+
+struct Tray<C: Connection> {
+    conn: C,
+    atoms: Atoms,
+    window: Window,
+    icons: Vec<Icon>,
+    icon_size: u16,
+    right_edge: i16,
+    // How much of the right end of the bar the tray covers, shared with the
+    // bar to keep its status text clear of it
+    width: Arc<AtomicU32>,
+}
+
+impl<C: Connection> Tray<C> {
+    fn icon(&mut self, window: Window) -> Option<&mut Icon> {
+        self.icons.iter_mut().find(|i| i.window == window)
+    }
+
+    // Icons without _XEMBED_INFO are always shown, the others say whether
+    // they want to be with its XEMBED_MAPPED flag
+    fn wants_mapping(&self, icon: Window) -> Result<bool> {
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                icon,
+                self.atoms._XEMBED_INFO,
+                self.atoms._XEMBED_INFO,
+                0,
+                2,
+            )
+            .map_err(x_err)?
+            .reply()
+            .map_err(x_err)?;
+
+        Ok(match reply.value32().map(|v| v.collect::<Vec<_>>()) {
+            Some(info) if info.len() >= 2 => info[1] & XEMBED_MAPPED != 0,
+            _ => true,
+        })
+    }
+
+    fn set_mapped(&mut self, icon: Window, mapped: bool) -> Result<()> {
+        if mapped {
+            self.conn.map_window(icon).map_err(x_err)?;
+        } else {
+            self.conn.unmap_window(icon).map_err(x_err)?;
+        }
+
+        if let Some(i) = self.icon(icon) {
+            i.mapped = mapped;
+        }
+        self.relayout()
+    }
+
+    fn dock(&mut self, icon: Window) -> Result<()> {
+        if self.icon(icon).is_some() {
+            return Ok(());
+        }
+
+        self.conn
+            .change_window_attributes(
+                icon,
+                &ChangeWindowAttributesAux::new()
+                    .event_mask(EventMask::STRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE),
+            )
+            .map_err(x_err)?;
+        // Puts the icon back on the root window if sswm goes away first
+        self.conn
+            .change_save_set(SetMode::INSERT, icon)
+            .map_err(x_err)?;
+        self.conn
+            .reparent_window(icon, self.window, 0, 0)
+            .map_err(x_err)?;
+
+        let notify = ClientMessageEvent::new(
+            32,
+            icon,
+            self.atoms._XEMBED,
+            [
+                CURRENT_TIME,
+                XEMBED_EMBEDDED_NOTIFY,
+                0,
+                self.window,
+                XEMBED_VERSION,
+            ],
+        );
+        self.conn
+            .send_event(false, icon, EventMask::NO_EVENT, notify)
+            .map_err(x_err)?;
+
+        self.icons.push(Icon {
+            window: icon,
+            mapped: false,
+        });
+        let mapped = self.wants_mapping(icon)?;
+        self.set_mapped(icon, mapped)
+    }
+
+    fn undock(&mut self, icon: Window) -> Result<()> {
+        if self.icon(icon).is_none() {
+            return Ok(());
+        }
+
+        self.icons.retain(|i| i.window != icon);
+        self.relayout()
+    }
+
+    // Icons are squares of the bar height and the tray window grows leftwards
+    // from the right edge of the monitor to fit the mapped ones.
+    fn relayout(&mut self) -> Result<()> {
+        let size = self.icon_size;
+        let mapped: Vec<Window> = self
+            .icons
+            .iter()
+            .filter(|i| i.mapped)
+            .map(|i| i.window)
+            .collect();
+
+        for (i, icon) in mapped.iter().enumerate() {
+            self.conn
+                .configure_window(
+                    *icon,
+                    &ConfigureWindowAux::new()
+                        .x((i as u16 * size) as i32)
+                        .y(0)
+                        .width(size as u32)
+                        .height(size as u32),
+                )
+                .map_err(x_err)?;
+        }
+
+        let width = mapped.len() as u16 * size;
+        if mapped.is_empty() {
+            self.conn.unmap_window(self.window).map_err(x_err)?;
+        } else {
+            self.conn
+                .configure_window(
+                    self.window,
+                    &ConfigureWindowAux::new()
+                        .x((self.right_edge - width as i16) as i32)
+                        .width(width as u32)
+                        .stack_mode(StackMode::ABOVE),
+                )
+                .map_err(x_err)?;
+            self.conn.map_window(self.window).map_err(x_err)?;
+        }
+        self.width.store(width as u32, Ordering::Relaxed);
+
+        self.conn.flush().map_err(x_err)?;
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        loop {
+            match self.conn.wait_for_event().map_err(x_err)? {
+                Event::ClientMessage(ev) if ev.type_ == self.atoms._NET_SYSTEM_TRAY_OPCODE => {
+                    let data = ev.data.as_data32();
+                    if data[1] == SYSTEM_TRAY_REQUEST_DOCK {
+                        self.dock(data[2])?;
+                    }
+                }
+                Event::MapRequest(ev) if self.icon(ev.window).is_some() => {
+                    self.set_mapped(ev.window, true)?;
+                }
+                // Icons hiding themselves give their space back
+                Event::UnmapNotify(ev) if self.icon(ev.window).is_some_and(|i| i.mapped) => {
+                    if let Some(i) = self.icon(ev.window) {
+                        i.mapped = false;
+                    }
+                    self.relayout()?;
+                }
+                Event::PropertyNotify(ev)
+                    if ev.atom == self.atoms._XEMBED_INFO && self.icon(ev.window).is_some() =>
+                {
+                    let mapped = self.wants_mapping(ev.window)?;
+                    if self.icon(ev.window).is_some_and(|i| i.mapped != mapped) {
+                        self.set_mapped(ev.window, mapped)?;
+                    }
+                }
+                // Icons don't get to pick their own size, so any resize is undone
+                Event::ConfigureRequest(ev) if self.icon(ev.window).is_some() => {
+                    self.relayout()?;
+                }
+                Event::DestroyNotify(ev) => self.undock(ev.window)?,
+                Event::ReparentNotify(ev) if ev.parent != self.window => self.undock(ev.window)?,
+                Event::SelectionClear(_) => {
+                    info!("system tray selection taken by another client");
+                    self.width.store(0, Ordering::Relaxed);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn run_tray(bar: &Bar, tray_width: Arc<AtomicU32>, area: Rect) -> Result<()> {
+    let (conn, screen_num) = x11rb::connect(None).map_err(x_err)?;
+    let screen = &conn.setup().roots[screen_num];
+    let root = screen.root;
+    let atoms = Atoms::new(&conn).map_err(x_err)?.reply().map_err(x_err)?;
+
+    let selection = conn
+        .intern_atom(false, format!("_NET_SYSTEM_TRAY_S{screen_num}").as_bytes())
+        .map_err(x_err)?
+        .reply()
+        .map_err(x_err)?
+        .atom;
+
+    let (x, y, width) = (area.x as i16, area.y as i16, area.w as u16);
+
+    let window = conn.generate_id().map_err(x_err)?;
+    conn.create_window(
+        COPY_DEPTH_FROM_PARENT,
+        window,
+        root,
+        x + width as i16 - 1,
+        y,
+        1,
+        bar.height as u16,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        0,
+        &CreateWindowAux::new()
+            .override_redirect(1)
+            // The bar colour is 0xRRGGBBAA, X wants the pixel as 0xRRGGBB
+            .background_pixel(bar.background >> 8)
+            .event_mask(EventMask::SUBSTRUCTURE_NOTIFY | EventMask::SUBSTRUCTURE_REDIRECT),
+    )
+    .map_err(x_err)?;
+    conn.change_property32(
+        PropMode::REPLACE,
+        window,
+        atoms._NET_SYSTEM_TRAY_ORIENTATION,
+        AtomEnum::CARDINAL,
+        &[0],
+    )
+    .map_err(x_err)?;
+
+    conn.set_selection_owner(window, selection, CURRENT_TIME)
+        .map_err(x_err)?;
+    let owner = conn
+        .get_selection_owner(selection)
+        .map_err(x_err)?
+        .reply()
+        .map_err(x_err)?
+        .owner;
+    if owner != window {
+        return Err(Error::Custom(
+            "another system tray is already running".into(),
+        ));
+    }
+
+    let manager = ClientMessageEvent::new(
+        32,
+        root,
+        atoms.MANAGER,
+        [CURRENT_TIME, selection, window, 0, 0],
+    );
+    conn.send_event(false, root, EventMask::STRUCTURE_NOTIFY, manager)
+        .map_err(x_err)?;
+    conn.flush().map_err(x_err)?;
+
+    let mut tray = Tray {
+        conn,
+        atoms,
+        window,
+        icons: vec![],
+        icon_size: bar.height as u16,
+        right_edge: x + width as i16,
+        width: tray_width,
+    };
+
+    tray.run()
+}
+
+// `area` is the screen whose bar gets the tray
+pub fn spawn_tray(bar: &Bar, width: Arc<AtomicU32>, area: Rect) {
+    let bar = bar.clone();
+
+    thread::spawn(move || {
+        if let Err(e) = run_tray(&bar, width, area) {
+            error!("system tray stopped: {e}");
+        }
+    });
+}
+
+// Blank space at the right end of the tray screen's bar as wide as the tray,
+// so the status text isn't drawn underneath the icons
+pub struct TraySpace {
+    bg: Color,
+    width: Arc<AtomicU32>,
+    drawn: u32,
+    // The bar is laid out and drawn one screen after the other, in order, so
+    // the screen the next extent is asked for follows the last one drawn
+    screen: usize,
+    next_screen: usize,
+    screens: usize,
+}
+
+impl TraySpace {
+    pub fn new(bg: Color, width: Arc<AtomicU32>, screen: usize) -> Self {
+        Self {
+            bg,
+            width,
+            drawn: 0,
+            screen,
+            next_screen: 0,
+            screens: 1,
+        }
+    }
+
+    fn width_on(&self, screen: usize) -> u32 {
+        if screen == self.screen {
+            self.width.load(Ordering::Relaxed)
+        } else {
+            0
+        }
+    }
+}
+
+impl Widget<RustConn> for TraySpace {
+    fn draw(
+        &mut self,
+        ctx: &mut Context<'_>,
+        screen: usize,
+        _screen_has_focus: bool,
+        w: u32,
+        h: u32,
+    ) -> penrose_ui::Result<()> {
+        ctx.fill_rect(Rect::new(0, 0, w, h), self.bg)?;
+        if screen == self.screen {
+            self.drawn = self.width_on(screen);
+        }
+        self.next_screen = (screen + 1) % self.screens;
+        Ok(())
+    }
+
+    fn current_extent(&mut self, _ctx: &mut Context<'_>, h: u32) -> penrose_ui::Result<(u32, u32)> {
+        Ok((self.width_on(self.next_screen), h))
+    }
+
+    fn is_greedy(&self) -> bool {
+        false
+    }
+
+    fn require_draw(&self) -> bool {
+        self.width_on(self.screen) != self.drawn
+    }
+
+    fn on_startup(&mut self, state: &mut State<RustConn>, x: &RustConn) -> penrose_ui::Result<()> {
+        self.on_refresh(state, x)
+    }
+
+    fn on_refresh(&mut self, state: &mut State<RustConn>, _x: &RustConn) -> penrose_ui::Result<()> {
+        self.screens = state.client_set.screens().count().max(1);
+        self.next_screen = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use penrose::{
+    builtin::actions::key_handler,
+    core::{
+        bindings::KeyEventHandler,
+        hooks::{EventHook, StateHook},
+        State,
+    },
+    x::{property::Prop, ClientAttr, XConn, XConnExt, XEvent},
+    x11rb::RustConn,
+    Color, Error, Result, Xid,
+};
+use std::sync::{Arc, Mutex};
+use x11rb::properties::WmHints;
+
+use crate::ipc::{self, protocol::Event};
+
+const DEMANDS_ATTENTION: &str = "_NET_WM_STATE_DEMANDS_ATTENTION";
+
+#[derive(Debug, Default)]
+struct Urgent {
+    // The window that asked most recently goes last
+    windows: Vec<Xid>,
+    tags: Vec<String>,
+}
+
+// Urgent windows and the tags holding them. Kept as a state extension, with a
+// clone handed to the bar, which draws without access to the state.
+#[derive(Debug, Default, Clone)]
+pub struct UrgentWindows(Arc<Mutex<Urgent>>);
+
+impl UrgentWindows {
+    pub fn is_urgent(&self, id: Xid) -> bool {
+        self.0.lock().unwrap().windows.contains(&id)
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.0.lock().unwrap().tags.clone()
+    }
+
+    fn last(&self) -> Option<Xid> {
+        self.0.lock().unwrap().windows.last().copied()
+    }
+
+    fn set(&self, id: Xid, urgent: bool) -> bool {
+        let mut all = self.0.lock().unwrap();
+        let was = all.windows.contains(&id);
+        all.windows.retain(|&u| u != id);
+        if urgent {
+            all.windows.push(id);
+        }
+
+        if was != urgent {
+            ipc::emit(Event::WindowUrgent {
+                id: u64::from(*id),
+                urgent,
+            });
+        }
+        was != urgent
+    }
+}
+
+// Empty if the extension was never added
+pub fn urgent_windows<X: XConn>(state: &State<X>) -> UrgentWindows {
+    state
+        .extension::<UrgentWindows>()
+        .map(|u| u.borrow().clone())
+        .unwrap_or_default()
+}
+
+fn x_err(e: impl std::fmt::Display) -> Error {
+    Error::Custom(e.to_string())
+}
+
+fn hints_urgent(id: Xid, x: &RustConn) -> Result<bool> {
+    let hints = WmHints::get(x.connection(), *id)
+        .map_err(x_err)?
+        .reply()
+        .map_err(x_err)?;
+
+    Ok(hints.is_some_and(|h| h.urgent))
+}
+
+fn net_wm_state(id: Xid, x: &RustConn) -> Vec<String> {
+    match x.get_prop(id, "_NET_WM_STATE") {
+        Ok(Some(Prop::Atom(atoms))) => atoms,
+        _ => vec![],
+    }
+}
+
+// _NET_WM_STATE belongs to the window manager, clients ask for changes with a
+// client message
+fn set_demands_attention(id: Xid, on: bool, x: &RustConn) -> Result<()> {
+    let mut atoms = net_wm_state(id, x);
+    if atoms.iter().any(|a| a == DEMANDS_ATTENTION) == on {
+        return Ok(());
+    }
+
+    atoms.retain(|a| a != DEMANDS_ATTENTION);
+    if on {
+        atoms.push(DEMANDS_ATTENTION.into());
+    }
+    x.set_prop(id, "_NET_WM_STATE", Prop::Atom(atoms))
+}
+
+fn update(id: Xid, urgent: bool, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+    // The focused window already has the user's attention
+    let urgent = urgent && state.client_set.current_client() != Some(&id);
+
+    if urgent_windows(state).set(id, urgent) {
+        x.refresh(state)?;
+    }
+    Ok(())
+}
+
+pub fn mark_urgent(id: Xid, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+    update(id, true, state, x)
+}
+
+pub fn focus_urgent() -> Box<dyn KeyEventHandler<RustConn>> {
+    key_handler(|state, x| match urgent_windows(state).last() {
+        Some(id) => {
+            state.client_set.focus_client(&id);
+            x.refresh(state)
+        }
+        None => Ok(()),
+    })
+}
+
+// Follows the urgency hint in WM_HINTS and _NET_WM_STATE_DEMANDS_ATTENTION
+pub struct Urgency;
+
+impl EventHook<RustConn> for Urgency {
+    fn call(&mut self, event: &XEvent, state: &mut State<RustConn>, x: &RustConn) -> Result<bool> {
+        match event {
+            XEvent::PropertyNotify(p)
+                if p.atom == "WM_HINTS" && state.client_set.contains(&p.id) =>
+            {
+                let urgent = hints_urgent(p.id, x)?
+                    || net_wm_state(p.id, x).iter().any(|a| a == DEMANDS_ATTENTION);
+                update(p.id, urgent, state, x)?;
+                Ok(true)
+            }
+            XEvent::ClientMessage(m)
+                if m.dtype == "_NET_WM_STATE" && state.client_set.contains(&m.id) =>
+            {
+                let data = m.data().as_usize();
+                let atom = *x.intern_atom(DEMANDS_ATTENTION)? as usize;
+                if data[1] != atom && data[2] != atom {
+                    return Ok(true);
+                }
+
+                // 0 removes the state, 1 adds it and 2 toggles it
+                let urgent = match data[0] {
+                    0 => false,
+                    1 => true,
+                    _ => !urgent_windows(state).is_urgent(m.id),
+                };
+                set_demands_attention(m.id, urgent, x)?;
+                update(m.id, urgent, state, x)?;
+                Ok(true)
+            }
+            _ => Ok(true),
+        }
+    }
+}
+
+// Clears the urgency of the focused window and draws the borders of the
+// urgent ones after every refresh
+pub struct UrgentBorders {
+    normal: u32,
+    urgent: u32,
+    painted: Vec<Xid>,
+}
+
+impl UrgentBorders {
+    pub fn new(normal: u32, urgent: u32) -> Self {
+        Self {
+            normal: Color::new_from_hex(normal).rgb_u32(),
+            urgent: Color::new_from_hex(urgent).rgb_u32(),
+            painted: vec![],
+        }
+    }
+}
+
+impl StateHook<RustConn> for UrgentBorders {
+    fn call(&mut self, state: &mut State<RustConn>, x: &RustConn) -> Result<()> {
+        let handle = urgent_windows(state);
+        let cs = &state.client_set;
+        let focused = cs.current_client().copied();
+
+        if let Some(id) = focused {
+            if handle.set(id, false) {
+                set_demands_attention(id, false, x)?;
+            }
+        }
+
+        let urgent = {
+            let mut all = handle.0.lock().unwrap();
+            all.windows.retain(|id| cs.contains(id));
+            let windows = all.windows.clone();
+            all.tags = cs
+                .ordered_tags()
+                .into_iter()
+                .filter(|tag| {
+                    cs.workspace(tag)
+                        .is_some_and(|ws| ws.clients().any(|id| windows.contains(id)))
+                })
+                .collect();
+            windows
+        };
+
+        // Penrose only redraws borders when the focus moves
+        for &id in &self.painted {
+            if !urgent.contains(&id) && Some(id) != focused && cs.contains(&id) {
+                x.set_client_attributes(id, &[ClientAttr::BorderColor(self.normal)])?;
+            }
+        }
+        for &id in &urgent {
+            x.set_client_attributes(id, &[ClientAttr::BorderColor(self.urgent)])?;
+        }
+
+        self.painted = urgent;
+        Ok(())
+    }
+}
@@ -1,11 +1,7 @@
 use penrose::{
     builtin::{
-        actions::{exit, floating::float_focused, key_handler, modify_with, send_layout_message},
-        layout::{
-            messages::{ExpandMain, IncMain, ShrinkMain},
-            transformers::{Gaps, ReflectHorizontal, ReserveTop},
-            MainAndStack, Monocle,
-        },
+        actions::{floating::float_focused, key_handler, modify_with, send_layout_message},
+        layout::messages::{ExpandMain, IncMain, ShrinkMain},
     },
     core::{
         bindings::{parse_keybindings_with_xmodmap, KeyEventHandler},
@@ -13,18 +9,65 @@ use penrose::{
         layout::LayoutStack,
         Config as PConfig, State, WindowManager,
     },
-    extensions::{actions::toggle_fullscreen, hooks::add_ewmh_hooks},
-    stack,
-    util::{self, spawn_with_args},
+    extensions::{
+        actions::toggle_fullscreen,
+        hooks::{
+            add_ewmh_hooks,
+            named_scratchpads::{add_named_scratchpads, NamedScratchPad},
+        },
+    },
+    pure::geometry::Rect,
     x::XConn,
     x11rb::RustConn,
-    Color, Result,
+    Color, Error, Result,
 };
-use std::{borrow::Cow, collections::HashMap};
-use tracing::Level;
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicU32, Arc},
+};
+use tracing::{warn, Level};
 use tracing_subscriber::{util::SubscriberInitExt, FmtSubscriber};
 
-use crate::config::Config;
+use crate::{
+    autostart::run_xdg_autostart,
+    config::{Command, Config, LayoutConfig, StartUp, TagConfig},
+    ipc::SocketGuard,
+    spawn::spawn,
+    startup::run_start_up,
+    supervisor,
+    x::{
+        bar::sswm_bar,
+        chords::Chords,
+        ipc::{layout_names, EventsHook, IpcHook},
+        keyboard::{apply_keyboard_config, switch_keyboard_layout},
+        layouts::{build_layout, LayoutParams},
+        modes::{KeyBindings, Modes},
+        mouse::mouse_bindings,
+        rules::{FocusStealingHook, RulesHook},
+        scratchpad::{named_scratchpads, ScratchpadClients, ToggleScratchpad},
+        struts::{toggle_struts, Docks, Struts},
+        tags::{NewWindowPosition, TagLayouts},
+        tray::spawn_tray,
+        urgency::{focus_urgent, Urgency, UrgentBorders, UrgentWindows},
+    },
+};
+
+fn spawn_handler(cmd: Command) -> Box<dyn KeyEventHandler<RustConn>> {
+    key_handler(move |_, _| {
+        spawn(&cmd);
+        Ok(())
+    })
+}
+
+// Penrose ends the process right away on exit, so the sockets go first
+fn exit() -> Box<dyn KeyEventHandler<RustConn>> {
+    key_handler(|state, _| {
+        if let Ok(sockets) = state.extension::<SocketGuard>() {
+            sockets.borrow_mut().remove();
+        }
+        std::process::exit(0)
+    })
+}
 
 #[derive(Default)]
 struct Xwm {
@@ -34,6 +77,16 @@ struct Xwm {
     outer_gaps: u32,
     inner_gaps: u32,
     top_gaps: u32,
+    layouts: Vec<LayoutConfig>,
+    tag_settings: Vec<TagConfig>,
+    named_scratchpads: Vec<NamedScratchPad<RustConn>>,
+    scratchpad_toggles: HashMap<String, ToggleScratchpad>,
+    scratchpad_clients: ScratchpadClients,
+    chords: Chords,
+    modes: Modes,
+    mode_names: Vec<String>,
+    mode_bindings: Vec<(String, KeyBindings)>,
+    struts: Struts,
 }
 
 impl Xwm {
@@ -58,27 +111,97 @@ impl Xwm {
         key_bindings
     }
 
+    fn layout_params(&self) -> LayoutParams {
+        LayoutParams {
+            max_main: self.max_main,
+            ratio: self.ratio,
+            ratio_step: self.ratio_step,
+            outer_gaps: self.outer_gaps,
+            inner_gaps: self.inner_gaps,
+            top_gaps: self.top_gaps,
+        }
+    }
+
+    // Settings for a tag win over the per-layout ones, which win over the
+    // top level config. A tag's default layout is focused in the stack, and
+    // added to it if it isn't one of the configured layouts.
+    fn layout_stack(&self, tag: Option<&TagConfig>) -> LayoutStack {
+        let mut configs = self.layouts.clone();
+        if configs.is_empty() {
+            configs = ["side", "sidereflected", "bottom", "monocle"]
+                .into_iter()
+                .map(|name| LayoutConfig {
+                    name: name.into(),
+                    ..Default::default()
+                })
+                .collect();
+        }
+
+        if let Some(name) = tag.and_then(|t| t.layout.as_ref()) {
+            if !configs.iter().any(|l| l.name.eq_ignore_ascii_case(name)) {
+                configs.insert(
+                    0,
+                    LayoutConfig {
+                        name: name.clone(),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+
+        let mut focused = 0;
+        let mut layouts = vec![];
+
+        for l in &configs {
+            let mut params = self.layout_params().with_overrides(l);
+            if let Some(tag) = tag {
+                params = params.with_tag_overrides(tag);
+            }
+
+            if let Some(layout) = build_layout(&l.name, params, &self.struts) {
+                if tag
+                    .and_then(|t| t.layout.as_ref())
+                    .is_some_and(|name| name.eq_ignore_ascii_case(&l.name))
+                {
+                    focused = layouts.len();
+                }
+                layouts.push(layout);
+            }
+        }
+
+        let mut stack = LayoutStack::try_from_iter(layouts).unwrap_or_else(|| {
+            let layouts = ["side", "sidereflected", "bottom", "monocle"]
+                .into_iter()
+                .filter_map(|name| build_layout(name, self.layout_params(), &self.struts));
+            LayoutStack::try_from_iter(layouts).unwrap()
+        });
+
+        for _ in 0..focused {
+            stack.focus_down();
+        }
+
+        stack
+    }
+
     fn layouts(&self) -> LayoutStack {
-        stack!(
-            MainAndStack::side(self.max_main, self.ratio, self.ratio_step),
-            ReflectHorizontal::wrap(MainAndStack::side(
-                self.max_main,
-                self.ratio,
-                self.ratio_step
-            )),
-            MainAndStack::bottom(self.max_main, self.ratio, self.ratio_step),
-            Monocle::boxed()
-        )
-        .map(|layout| {
-            ReserveTop::wrap(
-                Gaps::wrap(layout, self.outer_gaps, self.inner_gaps),
-                self.top_gaps,
-            )
-        })
+        self.layout_stack(None)
     }
 
-    fn match_actions(&self, action_str: &str) -> Option<Box<dyn KeyEventHandler<RustConn>>> {
-        match action_str.to_lowercase().as_str() {
+    fn tag_layouts(&self) -> Vec<(String, LayoutStack)> {
+        self.tag_settings
+            .iter()
+            .map(|t| (t.tag.clone(), self.layout_stack(Some(t))))
+            .collect()
+    }
+
+    fn match_actions(&mut self, action_str: &str) -> Option<Box<dyn KeyEventHandler<RustConn>>> {
+        let (action, arg) = action_str
+            .trim()
+            .split_once(' ')
+            .map(|(action, arg)| (action, arg.trim()))
+            .unwrap_or((action_str.trim(), ""));
+
+        match action.to_lowercase().as_str() {
             "kill" => Some(modify_with(|cs| cs.kill_focused())),
             "focusnext" => Some(modify_with(|cs| cs.focus_down())),
             "focusprevious" => Some(modify_with(|cs| cs.focus_up())),
@@ -95,11 +218,49 @@ impl Xwm {
             "decmain" => Some(send_layout_message(|| IncMain(-1))),
             "expandmain" => Some(send_layout_message(|| ExpandMain)),
             "shrinkmain" => Some(send_layout_message(|| ShrinkMain)),
+            "switchkeyboardlayout" => Some(switch_keyboard_layout()),
+            "togglestruts" => Some(toggle_struts()),
+            "focusurgent" => Some(focus_urgent()),
+            "focustag" => {
+                let tag = arg.to_string();
+                Some(modify_with(move |cs| cs.focus_tag(&tag)))
+            }
+            "movetotag" => {
+                let tag = arg.to_string();
+                Some(modify_with(move |cs| cs.move_focused_to_tag(&tag)))
+            }
+            // An unknown mode has no bindings and no Escape, so entering it
+            // would leave no way back out
+            "entermode" if self.mode_names.iter().any(|m| m == arg) => Some(self.modes.enter(arg)),
+            "entermode" => {
+                warn!("unknown mode: {arg}");
+                None
+            }
+            "exitmode" => Some(self.modes.exit()),
+            "togglescratchpad" => self
+                .scratchpad_toggles
+                .get(arg)
+                .cloned()
+                .map(|toggle| Box::new(toggle) as Box<dyn KeyEventHandler<RustConn>>),
             "exit" => Some(exit()),
             _ => None,
         }
     }
 
+    // Key sequences like `M-a t` go to the chords, single combos straight to penrose
+    fn bind(
+        &mut self,
+        keybinds: &mut HashMap<String, Box<dyn KeyEventHandler<RustConn>>>,
+        keybind: String,
+        handler: Box<dyn KeyEventHandler<RustConn>>,
+    ) {
+        if keybind.split_whitespace().count() > 1 {
+            self.chords.insert(&keybind, handler);
+        } else {
+            keybinds.insert(keybind, handler);
+        }
+    }
+
     fn handle_config(
         &mut self,
         cfg: Config,
@@ -110,38 +271,73 @@ impl Xwm {
         self.ratio_step = cfg.ratio_step;
         self.outer_gaps = cfg.outer_gaps;
         self.inner_gaps = cfg.inner_gaps;
-        self.top_gaps = cfg.top_gaps;
+        self.top_gaps = if cfg.bar.enabled {
+            cfg.bar.height
+        } else {
+            cfg.top_gaps
+        };
+        self.layouts = cfg.layouts.clone();
+        self.tag_settings = cfg.tag_settings.clone();
+        (
+            self.named_scratchpads,
+            self.scratchpad_toggles,
+            self.scratchpad_clients,
+        ) = named_scratchpads(&cfg.scratchpads);
+
+        self.chords = Chords::new(&cfg.key_chords);
+        self.mode_names = cfg.modes.iter().map(|m| m.name.clone()).collect();
 
         for i in cfg.commands {
-            keybinds.insert(
-                i.keybind,
-                key_handler(move |_, _| util::spawn(i.command.as_str())),
-            );
+            self.bind(&mut keybinds, i.keybind.clone(), spawn_handler(i));
         }
 
         for i in cfg.actions {
             let action_match: Option<Box<dyn KeyEventHandler<RustConn>>> =
                 self.match_actions(&i.action);
             if let Some(action) = action_match {
-                keybinds.insert(i.keybind, action);
+                self.bind(&mut keybinds, i.keybind, action);
             }
         }
 
         for i in cfg.xcommands {
-            keybinds.insert(
-                i.keybind,
-                key_handler(move |_, _| util::spawn(i.command.as_str())),
-            );
+            self.bind(&mut keybinds, i.keybind.clone(), spawn_handler(i));
         }
 
         for i in cfg.xactions {
             let action_match: Option<Box<dyn KeyEventHandler<RustConn>>> =
                 self.match_actions(&i.action);
             if let Some(action) = action_match {
-                keybinds.insert(i.keybind, action);
+                self.bind(&mut keybinds, i.keybind, action);
             }
         }
 
+        for mode in cfg.modes {
+            let mut bindings: KeyBindings = HashMap::new();
+
+            for i in mode.commands {
+                bindings.insert(i.keybind.clone(), spawn_handler(i));
+            }
+
+            for i in mode.actions {
+                if let Some(action) = self.match_actions(&i.action) {
+                    bindings.insert(i.keybind, action);
+                }
+            }
+
+            bindings
+                .entry("Escape".into())
+                .or_insert_with(|| self.modes.exit());
+            bindings.retain(|keybind, _| {
+                let single = keybind.split_whitespace().count() == 1;
+                if !single {
+                    warn!("key sequences aren't supported in modes: {keybind}");
+                }
+                single
+            });
+
+            self.mode_bindings.push((mode.name, bindings));
+        }
+
         keybinds
     }
 }
@@ -152,64 +348,124 @@ pub fn xrun(cfg: Config) -> Result<()> {
         .finish()
         .init();
 
-    let mut startup_hook1: String = "".into();
-
-    if !cfg.start_up.is_empty() {
-        for (e, i) in cfg.start_up.iter().enumerate() {
-            startup_hook1.push_str(i);
-            if cfg.start_up.len() > 1 && e + 1 < cfg.start_up.len() {
-                startup_hook1.push_str(" && ");
-            }
-        }
-    }
-
-    let mut startup_hook: Option<Box<dyn StateHook<RustConn>>> = None;
-
-    if !startup_hook1.is_empty() {
-        startup_hook = Some(SpawnOnStartupArg::boxed(startup_hook1));
-    }
+    apply_keyboard_config(&cfg.keyboard);
 
     let mut xwm = Xwm::default();
     let keybinds = xwm.handle_config(cfg.clone());
+    let mut keybinds = xwm.raw_key_bindings(keybinds);
+    for (prefix, handler) in xwm.chords.into_handlers()? {
+        if keybinds.insert(prefix.clone(), handler).is_some() {
+            warn!("{prefix} starts a key sequence, its own binding is ignored");
+        }
+    }
 
-    let config = add_ewmh_hooks(PConfig {
+    let mut config = add_ewmh_hooks(PConfig {
         default_layouts: xwm.layouts(),
         normal_border: Color::new_from_hex(cfg.normal_bordar),
         focused_border: Color::new_from_hex(cfg.focused_bordar),
-        startup_hook,
+        startup_hook: Some(Box::new(StartUpPrograms {
+            programs: cfg.start_up.iter().chain(&cfg.xstart_up).cloned().collect(),
+            xdg_autostart: cfg.xdg_autostart,
+        })),
+        // Has to run before the EWMH hook to stop it focusing the window
+        event_hook: Some(Box::new(FocusStealingHook::new(
+            cfg.focus_stealing,
+            &cfg.rules,
+        ))),
+        // Runs before the rules so a rule moving or floating the window wins
+        manage_hook: Some(Box::new(NewWindowPosition::new(&cfg.tag_settings))),
         tags: cfg.workspace_tags,
         ..PConfig::default()
     });
+    let tag_layouts = xwm.tag_layouts();
+    let ipc_layouts = tag_layouts
+        .iter()
+        .map(|(tag, stack)| (tag.clone(), layout_names(stack)))
+        .collect();
+    config.compose_or_set_manage_hook(RulesHook::new(&cfg.rules));
+    config.compose_or_set_manage_hook(xwm.scratchpad_clients.clone());
+    config.compose_or_set_manage_hook(Docks);
+    config.compose_or_set_startup_hook(TagLayouts::new(tag_layouts));
+    config.compose_or_set_event_hook(Docks);
+    config.compose_or_set_event_hook(Urgency);
+    config.compose_or_set_event_hook(xwm.chords.clone());
+    config.compose_or_set_event_hook(xwm.modes.clone());
+    config.compose_or_set_refresh_hook(UrgentBorders::new(cfg.normal_bordar, cfg.urgent_border));
+    let mut config = add_named_scratchpads(config, std::mem::take(&mut xwm.named_scratchpads));
 
     let conn = RustConn::new()?;
-    let key_bindings = parse_keybindings_with_xmodmap(xwm.raw_key_bindings(keybinds))?;
-    let wm = WindowManager::new(config, key_bindings, HashMap::new(), conn)?;
+    let keybinds = xwm
+        .modes
+        .wrap(keybinds, std::mem::take(&mut xwm.mode_bindings))?;
+    let key_bindings = parse_keybindings_with_xmodmap(keybinds)?;
+
+    let struts = xwm.struts.clone();
+    let urgent = UrgentWindows::default();
+
+    // Actions sent over the socket are looked up the same way as bound ones
+    let default_layouts = layout_names(&xwm.layouts());
+    let (ipc_hook, sockets) = IpcHook::new(
+        Box::new(move |action| xwm.match_actions(action)),
+        default_layouts,
+        ipc_layouts,
+        cfg.i3_ipc,
+    )?;
+    config.compose_or_set_event_hook(ipc_hook);
+    config.compose_or_set_refresh_hook(EventsHook::default());
+    let mut wm = WindowManager::new(
+        config,
+        key_bindings,
+        mouse_bindings(&cfg.mouse_bindings),
+        conn,
+    )?;
+    wm.state.add_extension(struts);
+    wm.state.add_extension(urgent.clone());
+    wm.state.add_extension(sockets);
+
+    if cfg.bar.enabled {
+        // The bar is drawn on penrose's screens in its order, the tray goes by
+        // the same order so it lands on the right bar
+        let screens: Vec<Rect> = wm
+            .state
+            .client_set
+            .screens()
+            .map(|s| s.geometry())
+            .collect();
+        let tray_screen = if cfg.bar.tray_monitor < screens.len() {
+            cfg.bar.tray_monitor
+        } else {
+            0
+        };
+        let tray_width = cfg.bar.tray.then(|| Arc::new(AtomicU32::new(0)));
+        let tray = tray_width.clone().map(|width| (width, tray_screen));
+        wm = sswm_bar(&cfg.bar, cfg.urgent_border, urgent, tray)?.add_to(wm);
+
+        if let (Some(width), Some(&area)) = (tray_width, screens.get(tray_screen)) {
+            spawn_tray(&cfg.bar, width, area);
+        }
+    }
 
     wm.run().unwrap();
     Ok(())
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SpawnOnStartupArg {
-    prog: Cow<'static, str>,
+pub struct StartUpPrograms {
+    programs: Vec<StartUp>,
+    xdg_autostart: bool,
 }
 
-impl SpawnOnStartupArg {
-    pub fn boxed<X>(prog: impl Into<Cow<'static, str>>) -> Box<dyn StateHook<X>>
-    where
-        X: XConn,
-    {
-        Box::new(Self { prog: prog.into() })
-    }
-}
-
-impl<X> StateHook<X> for SpawnOnStartupArg
+impl<X> StateHook<X> for StartUpPrograms
 where
     X: XConn,
 {
     fn call(&mut self, _state: &mut State<X>, _x: &X) -> Result<()> {
-        let args = ["-c", &self.prog];
-        spawn_with_args("bash", &args)?;
+        // Penrose sets SIGCHLD to SIG_IGN when it starts running, so the
+        // handler has to go in after that and before anything is spawned
+        supervisor::watch_sigchld().map_err(|e| Error::Custom(e.to_string()))?;
+        run_start_up(&self.programs);
+        if self.xdg_autostart {
+            run_xdg_autostart();
+        }
         Ok(())
     }
 }